@@ -1,41 +1,92 @@
 use std::net::TcpStream;
-use std::io::{self, Write, Read};
+use std::io;
 use std::thread;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use protocol::*;
 
 fn main() -> std::io::Result<()> {
     let mut stream = TcpStream::connect("0.0.0.0:8080")?;
-    let mut cloned_stream = stream.try_clone()?;
+    let mut reader = FrameReader::new(stream.try_clone()?);
     println!("Chat open:");
 
+    // the server greets every connection with a plain text banner before the handshake
+    match reader.read_frame() {
+        Ok(banner) => println!("{}", banner),
+        Err(e) => {
+            eprintln!("Error while reading stream: {}", e);
+            return Ok(());
+        }
+    }
+    send_request(&mut stream, &Request::Hello { version: PROTOCOL_VERSION });
+
+    let logged_in = Arc::new(AtomicBool::new(false));
+    let in_game = Arc::new(AtomicBool::new(false));
+    let reader_logged_in = logged_in.clone();
+    let reader_in_game = in_game.clone();
+
     thread::spawn(move ||
         loop {
-
-            let mut read_buffer = [0; 1024];
-    
-            let bytes_read = cloned_stream.read(&mut read_buffer).unwrap_or_else(|e| {
-                eprintln!("Error while reading stream: {}", e);
-                return 0;
-            });
-    
-            let message_len = match read_buffer.iter().position(|&x| x == b'\0') {
-                Some(index) => index,
-                None => bytes_read
-            };
-            
-            let message = String::from_utf8_lossy(&read_buffer[..message_len]).to_string();
-            println!("{}", message);    
+            match reader.read_frame() {
+                Ok(frame) => match parse_json_message(&frame) {
+                    Some(message) => {
+                        track_state(&message, &reader_logged_in, &reader_in_game);
+                        println!("{}", message);
+                    }
+                    None => println!("{}", frame),
+                },
+                Err(FrameError::Closed) => {
+                    println!("Server closed the connection");
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error while reading stream: {}", e);
+                    std::process::exit(1);
+                }
+            }
         }
     );
 
     loop {
-        let mut msg_buffer = String::new(); 
+        let mut msg_buffer = String::new();
         io::stdin().read_line(&mut msg_buffer).unwrap();
         let msg = msg_buffer.trim().to_string();
         if msg == "quit" {break}
-        stream.write(msg.as_bytes()).unwrap();
-        stream.flush().unwrap();
+
+        if !logged_in.load(Ordering::SeqCst) {
+            match parse_login_command(&msg) {
+                Ok(request) => send_request(&mut stream, &request),
+                Err(usage) => println!("{}", usage),
+            }
+            continue
+        }
+        match parse_text_command(&msg, in_game.load(Ordering::SeqCst)) {
+            Ok(Some(request)) => send_request(&mut stream, &request),
+            Ok(None) => {}
+            Err(usage) => println!("{}", usage),
+        }
     }
 
     println!("Closing chat...");
     Ok(())
-}
\ No newline at end of file
+}
+
+fn send_request(stream: &mut TcpStream, request: &Request) {
+    if let Err(e) = write_frame(stream, &encode_request(request)) {
+        eprintln!("Error while writing stream: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// The text commands available depend on whether we are logged in and in a game,
+/// which we only learn from the messages the server sends.
+fn track_state(message: &ServerMessage, logged_in: &AtomicBool, in_game: &AtomicBool) {
+    match message {
+        ServerMessage::Response(Response::LoggedIn { .. }) => logged_in.store(true, Ordering::SeqCst),
+        ServerMessage::Response(Response::Resumed { .. }) => logged_in.store(true, Ordering::SeqCst),
+        ServerMessage::Event(Event::GameRecovered) => in_game.store(true, Ordering::SeqCst),
+        ServerMessage::Event(Event::BoardUpdate { to_move: Some(_), spectating: false, .. }) => in_game.store(true, Ordering::SeqCst),
+        ServerMessage::Event(Event::GameOver { .. }) => in_game.store(false, Ordering::SeqCst),
+        _ => {}
+    }
+}
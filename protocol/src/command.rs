@@ -0,0 +1,438 @@
+use std::fmt;
+
+use crate::message::*;
+
+/// A text command that doesn't match its usage, carrying the usage line to show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsageError(pub &'static str);
+
+impl UsageError {
+    pub fn to_message(self) -> ServerMessage {
+        ServerMessage::error(ErrorCode::Usage, self.0)
+    }
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses a line from a text mode client that hasn't logged in yet.
+pub fn parse_login_command(message: &str) -> Result<Request, UsageError> {
+    let words = message.split_whitespace().collect::<Vec<&str>>();
+    match words.as_slice() {
+        ["register", username, password] => Ok(Request::Register { username: username.to_string(), password: password.to_string() }),
+        ["login", username, password] => Ok(Request::Login { username: username.to_string(), password: password.to_string() }),
+        ["resume", token] => Ok(Request::Resume { token: token.to_string() }),
+        _ => Err(UsageError("use format: register <user> <password>, login <user> <password> or resume <token>")),
+    }
+}
+
+/// Parses a line from a text mode client. Which commands are available depends
+/// on whether the player is currently in a game, just like the original interface.
+/// In a game a single word is a move and anything longer is chat for the game.
+pub fn parse_text_command(message: &str, in_game: bool) -> Result<Option<Request>, UsageError> {
+    let words = message.split_whitespace().collect::<Vec<&str>>();
+    let command = words.first().copied().unwrap_or("");
+
+    if in_game {
+        return Ok(match command {
+            "resign" => Some(Request::Resign),
+            square if words.len() == 1 => Some(Request::Move { square: square.to_string() }),
+            "" => None,
+            _ => Some(Request::GameChat { text: message.to_string() }),
+        });
+    }
+
+    match command {
+        "online" => Ok(Some(Request::Online)),
+        "games" => Ok(Some(Request::Games)),
+        "unwatch" => Ok(Some(Request::Unwatch)),
+        "history" => match words.as_slice() {
+            [_] => Ok(Some(Request::History { player: None })),
+            [_, player] => Ok(Some(Request::History { player: Some(player.to_string()) })),
+            _ => Err(UsageError("use format: history [<user>]")),
+        },
+        "show" => match words.as_slice() {
+            [_, id] => id.trim_start_matches('#').parse()
+                .map(|id| Some(Request::Show { id }))
+                .map_err(|_| UsageError("use format: show <game id>")),
+            _ => Err(UsageError("use format: show <game id>")),
+        },
+        "stats" => match words.as_slice() {
+            [_] => Ok(Some(Request::Stats { player: None })),
+            [_, player] => Ok(Some(Request::Stats { player: Some(player.to_string()) })),
+            _ => Err(UsageError("use format: stats [<user>]")),
+        },
+        "leaderboard" => match words.as_slice() {
+            [_] => Ok(Some(Request::Leaderboard { limit: None })),
+            [_, limit] => limit.parse()
+                .map(|limit| Some(Request::Leaderboard { limit: Some(limit) }))
+                .map_err(|_| UsageError("use format: leaderboard [<number of players>]")),
+            _ => Err(UsageError("use format: leaderboard [<number of players>]")),
+        },
+        "rating-history" => match words.as_slice() {
+            [_, player] => Ok(Some(Request::RatingHistory { player: player.to_string() })),
+            _ => Err(UsageError("use format: rating-history <user>")),
+        },
+        "export" => match words.as_slice() {
+            [_, id] => id.trim_start_matches('#').parse()
+                .map(|id| Some(Request::Export { id }))
+                .map_err(|_| UsageError("use format: export <game id>")),
+            _ => Err(UsageError("use format: export <game id>")),
+        },
+        "replay" => {
+            let usage = || UsageError("use format: replay <game id> [<seconds per move>]");
+            let id = match words.get(1).map(|id| id.trim_start_matches('#').parse()) {
+                Some(Ok(id)) => id,
+                _ => return Err(usage()),
+            };
+            let interval_ms = match &words[2..] {
+                [] => None,
+                [seconds] => match seconds.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Some((seconds * 1000.0) as u64),
+                    _ => return Err(usage()),
+                },
+                _ => return Err(usage()),
+            };
+            Ok(Some(Request::Replay { id, interval_ms }))
+        }
+        "next" if words.len() == 1 => Ok(Some(Request::ReplayNext)),
+        "prev" if words.len() == 1 => Ok(Some(Request::ReplayPrev)),
+        "watch" => {
+            if words.len() == 2 {
+                Ok(Some(Request::Watch { player: words[1].to_string() }))
+            }
+            else {
+                Err(UsageError("use format: watch <user>"))
+            }
+        }
+        "dm" => {
+            if words.len() >= 3 {
+                Ok(Some(Request::Dm { to: words[1].to_string(), text: words[2..].join(" ") }))
+            }
+            else {
+                Err(UsageError("use format: dm <user> <message>"))
+            }
+        }
+        "challenge" => {
+            let usage = || UsageError("use format: challenge <user> [<game>] [<base seconds>+<increment seconds>] [<position>] [x|o|random] [rated|casual] or challenge bot [easy|medium|perfect] [<game>] [<base seconds>+<increment seconds>] [<position>] [x|o|random]");
+            if words.len() < 2 {
+                return Err(usage());
+            }
+            let opponent = words[1];
+            let mut variant = None;
+            let mut time_control = None;
+            let mut difficulty = None;
+            let mut position = None;
+            let mut color = None;
+            let mut rated = None;
+            for option in &words[2..] {
+                if option.contains(':') {
+                    if position.replace(option.to_string()).is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if let Ok(tc) = option.parse::<TimeControl>() {
+                    if time_control.replace(tc).is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if let (BOT_NAME, Ok(level)) = (opponent, option.parse::<Difficulty>()) {
+                    if difficulty.replace(level).is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if let Ok(side) = option.parse::<ColorChoice>() {
+                    if color.replace(side).is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if *option == "rated" || *option == "casual" {
+                    if rated.replace(*option == "rated").is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if variant.replace(option.to_string()).is_some() {
+                    return Err(usage());
+                }
+            }
+            Ok(Some(Request::Challenge { opponent: opponent.to_string(), variant, time_control, difficulty, position, color, rated }))
+        }
+        "seek" => {
+            let usage = || UsageError("use format: seek [<game>] [<base seconds>+<increment seconds>] [rated|casual]");
+            let mut variant = None;
+            let mut time_control = None;
+            let mut rated = None;
+            for option in &words[1..] {
+                if let Ok(tc) = option.parse::<TimeControl>() {
+                    if time_control.replace(tc).is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if *option == "rated" || *option == "casual" {
+                    if rated.replace(*option == "rated").is_some() {
+                        return Err(usage());
+                    }
+                }
+                else if variant.replace(option.to_string()).is_some() {
+                    return Err(usage());
+                }
+            }
+            Ok(Some(Request::Seek { variant, time_control, rated: rated.unwrap_or(false) }))
+        }
+        "cancel-seek" => Ok(Some(Request::CancelSeek)),
+        "accept" => {
+            if words.len() == 2 {
+                Ok(Some(Request::Accept { opponent: words[1].to_string() }))
+            }
+            else {
+                Err(UsageError("use format: accept <user>"))
+            }
+        }
+        "decline" => match words.as_slice() {
+            [_, opponent] => Ok(Some(Request::Decline { opponent: opponent.to_string() })),
+            _ => Err(UsageError("use format: decline <user>")),
+        },
+        "withdraw" => match words.as_slice() {
+            [_, opponent] => Ok(Some(Request::Withdraw { opponent: opponent.to_string() })),
+            _ => Err(UsageError("use format: withdraw <user>")),
+        },
+        "challenges" if words.len() == 1 => Ok(Some(Request::Challenges)),
+        _ => Ok(Some(Request::Chat { text: message.to_string() })),
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::X => write!(f, "X"),
+            Outcome::O => write!(f, "O"),
+            Outcome::Draw => write!(f, "Draw"),
+        }
+    }
+}
+
+/// The text mode rendering, matching what the server sent before the structured protocol.
+impl fmt::Display for ServerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerMessage::Response(response) => match response {
+                Response::Hello { version } => write!(f, "protocol version {}", version),
+                Response::LoggedIn { username, token } => write!(f, "Welcome {}!\nreconnect token: {}", username, token),
+                Response::Resumed { username, token } => write!(f, "Welcome back {}, your session was resumed\nreconnect token: {}", username, token),
+                Response::DmSent { to } => write!(f, "dm sent to {}", to),
+                Response::ChallengeSent { to } => write!(f, "challenge sent to {}", to),
+                Response::ChallengeAccepted { opponent } => writeln!(f, "accepted challege with {}", opponent),
+                Response::ChallengeDeclined { from } => write!(f, "declined the challenge from {}", from),
+                Response::ChallengeWithdrawn { to } => write!(f, "withdrew your challenge to {}", to),
+                Response::Challenges { incoming, outgoing } if incoming.is_empty() && outgoing.is_empty() => write!(f, "No open challenges"),
+                Response::Challenges { incoming, outgoing } => {
+                    write!(f, "Challenges:")?;
+                    for challenge in incoming {
+                        write!(f, "\nfrom {}: {}", challenge.player, describe_challenge(challenge))?;
+                    }
+                    for challenge in outgoing {
+                        write!(f, "\nto {}: {}", challenge.player, describe_challenge(challenge))?;
+                    }
+                    Ok(())
+                }
+                Response::Seeking { variant, time_control, rated } => {
+                    write!(f, "looking for an opponent for {}", variant)?;
+                    if let Some(tc) = time_control {
+                        write!(f, " ({})", tc)?;
+                    }
+                    write!(f, ", {}, type cancel-seek to stop", if *rated { "rated" } else { "casual" })
+                }
+                Response::SeekCancelled => write!(f, "stopped looking for an opponent"),
+                Response::Online { players } => write!(f, "Online players:\n{}", players.join("  ")),
+                Response::Games { games } if games.is_empty() => write!(f, "No games are being played"),
+                Response::Games { games } => {
+                    write!(f, "Games being played:")?;
+                    for game in games {
+                        write!(f, "\n{}: {} (X) vs {} (O), {} watching", game.variant, game.x, game.o, game.spectators)?;
+                    }
+                    Ok(())
+                }
+                Response::Watching { x, o } => write!(f, "watching {} vs {}, type unwatch to stop", x, o),
+                Response::Unwatched => write!(f, "stopped watching"),
+                Response::History { player, games } if games.is_empty() => write!(f, "{} has no finished games", player),
+                Response::History { player, games } => {
+                    write!(f, "Games of {}:", player)?;
+                    for game in games {
+                        write!(f, "\n#{} {}: {} (X) vs {} (O), {}, {} moves", game.id, game.variant, game.x, game.o, describe_result(game.outcome, game.reason), game.move_count)?;
+                    }
+                    Ok(())
+                }
+                Response::Game { game } => {
+                    write!(f, "#{} {}: {} (X) vs {} (O)", game.id, game.variant, game.x, game.o)?;
+                    if let Some(tc) = game.time_control {
+                        write!(f, " {}", tc)?;
+                    }
+                    if let Some(position) = &game.start_position {
+                        write!(f, " from {}", position)?;
+                    }
+                    if game.rated {
+                        write!(f, " rated")?;
+                    }
+                    let first = if game.first_to_move() == Outcome::X { 0 } else { 1 };
+                    for (i, mv) in game.moves.iter().enumerate() {
+                        let side = if (i + first) % 2 == 0 { "X" } else { "O" };
+                        write!(f, "\n{}. {} {} ({})", i + 1, side, mv.mv, format_clock(mv.at_ms))?;
+                    }
+                    write!(f, "\n{}", describe_result(game.outcome, game.reason))
+                }
+                Response::Stats { player, stats } => write!(f, "{}: rating {}, {} games, {} wins, {} losses ({} resigned), {} draws",
+                    player, stats.rating, stats.games, stats.wins, stats.losses, stats.resignations, stats.draws),
+                Response::Leaderboard { players } if players.is_empty() => write!(f, "Nobody has played a rated game yet"),
+                Response::Leaderboard { players } => {
+                    write!(f, "Leaderboard:")?;
+                    for (i, entry) in players.iter().enumerate() {
+                        write!(f, "\n{:>3}. {:<20} {:>5}  {} games", i + 1, entry.username, entry.rating, entry.rated_games)?;
+                    }
+                    Ok(())
+                }
+                Response::RatingHistory { player, points } if points.is_empty() => write!(f, "{} has no rated games", player),
+                Response::RatingHistory { player, points } => {
+                    write!(f, "Rating of {}:", player)?;
+                    for point in points {
+                        write!(f, "\n{} #{} {}", format_date(point.at), point.game, point.rating)?;
+                    }
+                    Ok(())
+                }
+                Response::Export { notation, .. } => write!(f, "{}", notation.trim_end()),
+                Response::Error { message, .. } => write!(f, "{}", message),
+            },
+            ServerMessage::Event(event) => match event {
+                Event::Chat { from, text } => write!(f, "{}: {}", from, text),
+                Event::GameChat { from, text } => write!(f, "[game] {}: {}", from, text),
+                Event::DirectMessage { from, text } => write!(f, "dm from {}: {}", from, text),
+                Event::ChallengeReceived { from, variant, time_control, position, rated, color } => {
+                    write!(f, "{} challenges you to {}", from, variant)?;
+                    if let Some(tc) = time_control {
+                        write!(f, " ({})", tc)?;
+                    }
+                    if let Some(position) = position {
+                        write!(f, " from {}", position)?;
+                    }
+                    write!(f, ", {}", if *rated { "rated" } else { "casual" })?;
+                    match color {
+                        ColorChoice::X => write!(f, ", you play O")?,
+                        ColorChoice::O => write!(f, ", you play X")?,
+                        ColorChoice::Random => write!(f, ", colors drawn at random")?,
+                    }
+                    write!(f, "\nType: accept {} to play or decline {} to turn it down", from, from)
+                }
+                Event::ChallengeAccepted { by } => write!(f, "{} has accepted your challange", by),
+                Event::ChallengeClosed { from, to, reason } => match reason {
+                    ChallengeEnd::Declined => write!(f, "{} declined the challenge from {}", to, from),
+                    ChallengeEnd::Withdrawn => write!(f, "{} withdrew the challenge to {}", from, to),
+                    ChallengeEnd::Expired => write!(f, "the challenge from {} to {} expired", from, to),
+                    ChallengeEnd::ChallengerLeft => write!(f, "{} left, the challenge to {} is off", from, to),
+                    ChallengeEnd::ChallengedLeft => write!(f, "{} left, the challenge from {} is off", to, from),
+                },
+                Event::ChallengePartyOffline { player } => write!(f, "{} went offline, your challenge with them is on hold until they are back", player),
+                Event::Matched { opponent, opponent_rating } => write!(f, "found an opponent: {} ({})", opponent, opponent_rating),
+                Event::SessionTakenOver => write!(f, "you logged in from another connection, closing this one"),
+                Event::GameRecovered => write!(f, "game successfully recovered"),
+                Event::ForfeitCountdown { player, seconds_left } => write!(f, "{} is disconnected and forfeits in {} seconds unless they reconnect", player, seconds_left),
+                Event::OpponentReconnected { player } => write!(f, "{} reconnected", player),
+                Event::BoardUpdate { x, o, board, clocks, to_move, move_hint, your_turn, spectating, .. } => {
+                    write!(f, "\nX: {} O: {}", x, o)?;
+                    if let Some(clocks) = clocks {
+                        write!(f, "\nclock X {} O {}", format_clock(clocks.x_ms), format_clock(clocks.o_ms))?;
+                    }
+                    for row in board {
+                        write!(f, "\n{}", row.join(" "))?;
+                    }
+                    match (to_move, your_turn) {
+                        (None, _) => Ok(()),
+                        (Some(player), _) if *spectating => write!(f, "\n{} to move", player),
+                        (Some(_), true) => write!(f, "\nYour turn: {}", move_hint),
+                        (Some(_), false) => write!(f, "\nWaiting for opponent..."),
+                    }
+                }
+                Event::ReplayStep { id, ply, total, last_move, last_side, outcome, reason } => {
+                    write!(f, "replay of #{}, ", id)?;
+                    match (last_move, last_side) {
+                        (Some(mv), Some(side)) => write!(f, "move {} of {}: {} {}", ply, total, side, mv)?,
+                        _ => write!(f, "start, {} moves", total)?,
+                    }
+                    match (outcome, reason) {
+                        (Some(outcome), Some(reason)) => write!(f, ", {}", describe_result(*outcome, *reason)),
+                        _ => Ok(()),
+                    }
+                }
+                Event::GameOver { outcome, reason, loser, .. } => match (reason, loser) {
+                    (EndReason::Resignation, Some(loser)) => writeln!(f, "{} resigned the game", loser),
+                    (EndReason::Timeout, Some(loser)) => writeln!(f, "{} ran out of time, {} Wins!", loser, outcome),
+                    (EndReason::EngineFailure, Some(loser)) => writeln!(f, "{} failed to make a move and forfeits, {} Wins!", loser, outcome),
+                    (EndReason::Forfeit, Some(loser)) => writeln!(f, "{} did not reconnect in time and forfeits, {} Wins!", loser, outcome),
+                    (_, _) if *outcome == Outcome::Draw => writeln!(f, "Draw!"),
+                    (_, _) => writeln!(f, "{} Wins!", outcome),
+                },
+            },
+        }
+    }
+}
+
+/// A short description of how a game ended, like "X won on time".
+fn describe_result(outcome: Outcome, reason: EndReason) -> String {
+    let how = match reason {
+        EndReason::Line => "with a line",
+        EndReason::BoardFull => "board full",
+        EndReason::Resignation => "by resignation",
+        EndReason::Forfeit => "by forfeit",
+        EndReason::Timeout => "on time",
+        EndReason::EngineFailure => "by engine failure",
+    };
+    match outcome {
+        Outcome::Draw => format!("draw, {}", how),
+        winner => format!("{} won {}", winner, how),
+    }
+}
+
+/// What an open challenge is for, like "tictactoe (60+2), rated, challenger plays X, expires in 45s".
+fn describe_challenge(challenge: &PendingChallenge) -> String {
+    let mut description = challenge.variant.clone();
+    if let Some(tc) = challenge.time_control {
+        description.push_str(&format!(" ({})", tc));
+    }
+    if let Some(position) = &challenge.position {
+        description.push_str(&format!(" from {}", position));
+    }
+    description.push_str(if challenge.rated { ", rated" } else { ", casual" });
+    description.push_str(match challenge.color {
+        ColorChoice::X => ", challenger plays X",
+        ColorChoice::O => ", challenger plays O",
+        ColorChoice::Random => ", colors drawn at random",
+    });
+    description.push_str(&format!(", expires in {}s", challenge.expires_in));
+    description
+}
+
+/// Renders seconds since the Unix epoch as a UTC date like 2024-03-01.
+fn format_date(secs: u64) -> String {
+    // days to a civil date, after Howard Hinnant's days_from_civil inverse
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
+/// Renders milliseconds as m:ss, with tenths once there are less than ten seconds left.
+fn format_clock(ms: u64) -> String {
+    if ms < 10_000 {
+        format!("0:{:02}.{}", ms / 1000, ms % 1000 / 100)
+    }
+    else {
+        format!("{}:{:02}", ms / 60_000, ms / 1000 % 60)
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// Every frame on the wire is a 4 byte big-endian payload length followed by the UTF-8 payload.
+const HEADER_LEN: usize = 4;
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+const READ_CHUNK: usize = 4096;
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The peer closed the connection between two frames.
+    Closed,
+    /// The peer closed the connection in the middle of a frame.
+    Truncated,
+    /// A frame announced (or tried to send) a payload larger than `MAX_FRAME_SIZE`.
+    TooLarge(usize),
+    InvalidUtf8,
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Closed => write!(f, "connection closed"),
+            FrameError::Truncated => write!(f, "connection closed in the middle of a frame"),
+            FrameError::TooLarge(len) => write!(f, "frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_SIZE),
+            FrameError::InvalidUtf8 => write!(f, "frame payload is not valid UTF-8"),
+            FrameError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// Buffers partial reads so that exactly one logical message is returned per call,
+/// no matter how TCP splits or merges the underlying bytes.
+pub struct FrameReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> FrameReader<R> {
+        FrameReader {
+            inner,
+            buffer: vec![],
+        }
+    }
+
+    pub fn read_frame(&mut self) -> Result<String, FrameError> {
+        loop {
+            if let Some(frame) = self.next_buffered_frame()? {
+                return Ok(frame);
+            }
+            let mut chunk = [0; READ_CHUNK];
+            let bytes_read = match self.inner.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if bytes_read == 0 {
+                return Err(if self.buffer.is_empty() { FrameError::Closed } else { FrameError::Truncated });
+            }
+            self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        }
+    }
+
+    fn next_buffered_frame(&mut self) -> Result<Option<String>, FrameError> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&self.buffer[..HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(FrameError::TooLarge(len));
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let payload: Vec<u8> = self.buffer.drain(..HEADER_LEN + len).skip(HEADER_LEN).collect();
+        String::from_utf8(payload).map(Some).map_err(|_| FrameError::InvalidUtf8)
+    }
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, message: &str) -> Result<(), FrameError> {
+    let payload = message.as_bytes();
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(payload.len()));
+    }
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out one byte per read, like the slowest possible network.
+    struct Trickle(Vec<u8>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0.remove(0);
+            Ok(1)
+        }
+    }
+
+    fn frame(message: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_frame(&mut bytes, message).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_frames_split_over_many_reads() {
+        let mut bytes = frame("hello");
+        bytes.extend(frame("wörld"));
+        let mut reader = FrameReader::new(Trickle(bytes));
+        assert_eq!(reader.read_frame().unwrap(), "hello");
+        assert_eq!(reader.read_frame().unwrap(), "wörld");
+        assert!(matches!(reader.read_frame(), Err(FrameError::Closed)));
+    }
+
+    #[test]
+    fn reads_frames_merged_into_one_read() {
+        let mut bytes = frame("first");
+        bytes.extend(frame(""));
+        bytes.extend(frame("second"));
+        let mut reader = FrameReader::new(bytes.as_slice());
+        assert_eq!(reader.read_frame().unwrap(), "first");
+        assert_eq!(reader.read_frame().unwrap(), "");
+        assert_eq!(reader.read_frame().unwrap(), "second");
+        assert!(matches!(reader.read_frame(), Err(FrameError::Closed)));
+    }
+
+    #[test]
+    fn tells_a_cut_off_frame_from_a_closed_connection() {
+        let bytes = frame("cut off");
+        let mut reader = FrameReader::new(&bytes[..bytes.len() - 1]);
+        assert!(matches!(reader.read_frame(), Err(FrameError::Truncated)));
+        let mut reader = FrameReader::new(&bytes[..2]);
+        assert!(matches!(reader.read_frame(), Err(FrameError::Truncated)));
+        let mut reader = FrameReader::new(&[][..]);
+        assert!(matches!(reader.read_frame(), Err(FrameError::Closed)));
+    }
+
+    #[test]
+    fn rejects_frames_over_the_size_limit() {
+        let too_large = "x".repeat(MAX_FRAME_SIZE + 1);
+        let mut bytes = vec![];
+        assert!(matches!(write_frame(&mut bytes, &too_large), Err(FrameError::TooLarge(len)) if len == MAX_FRAME_SIZE + 1));
+        assert!(bytes.is_empty());
+        assert!(write_frame(&mut bytes, &too_large[1..]).is_ok());
+
+        // only the header is needed to turn the frame away
+        let header = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes();
+        let mut reader = FrameReader::new(&header[..]);
+        assert!(matches!(reader.read_frame(), Err(FrameError::TooLarge(len)) if len == MAX_FRAME_SIZE + 1));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        let mut bytes = 2u32.to_be_bytes().to_vec();
+        bytes.extend([0xc3, 0x28]);
+        let mut reader = FrameReader::new(bytes.as_slice());
+        assert!(matches!(reader.read_frame(), Err(FrameError::InvalidUtf8)));
+    }
+}
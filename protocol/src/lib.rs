@@ -0,0 +1,16 @@
+//! The wire protocol spoken between the Tic Tac Toe server and its clients.
+//!
+//! `framing` turns a byte stream into length-prefixed frames, `message` holds the typed
+//! requests, responses and events and their JSON encoding, `command` maps the plain
+//! text command interface onto those types and `notation` writes and reads finished games
+//! as text.
+
+pub mod command;
+pub mod framing;
+pub mod message;
+pub mod notation;
+
+pub use command::*;
+pub use framing::*;
+pub use message::*;
+pub use notation::*;
@@ -0,0 +1,500 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Challenging this name starts a game against the server's built-in bot.
+pub const BOT_NAME: &str = "bot";
+
+/// Bumped whenever a message changes shape in a way older clients can't read.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Base time plus a per move increment, written like `60+2`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub base_secs: u64,
+    pub increment_secs: u64,
+}
+
+impl FromStr for TimeControl {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TimeControl, ()> {
+        let (base, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let base_secs = base.parse().map_err(|_| ())?;
+        let increment_secs = increment.parse().map_err(|_| ())?;
+        if base_secs == 0 {
+            return Err(());
+        }
+        Ok(TimeControl { base_secs, increment_secs })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.base_secs, self.increment_secs)
+    }
+}
+
+/// How hard the server's built-in bot plays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    /// Random legal moves.
+    Easy,
+    /// Looks a couple of moves ahead, so it takes wins and blocks threats.
+    #[default]
+    Medium,
+    /// Searches as deep as it can, which is the whole game for the smaller boards.
+    Perfect,
+}
+
+impl FromStr for Difficulty {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Difficulty, ()> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "perfect" => Ok(Difficulty::Perfect),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Medium => write!(f, "medium"),
+            Difficulty::Perfect => write!(f, "perfect"),
+        }
+    }
+}
+
+/// Which side the challenger plays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorChoice {
+    #[default]
+    X,
+    O,
+    /// Decided by a coin toss when the challenge is accepted.
+    Random,
+}
+
+impl FromStr for ColorChoice {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ColorChoice, ()> {
+        match s {
+            "x" => Ok(ColorChoice::X),
+            "o" => Ok(ColorChoice::O),
+            "random" => Ok(ColorChoice::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorChoice::X => write!(f, "x"),
+            ColorChoice::O => write!(f, "o"),
+            ColorChoice::Random => write!(f, "random"),
+        }
+    }
+}
+
+/// Everything a client can ask the server to do.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Hello { version: u32 },
+    Register { username: String, password: String },
+    Login { username: String, password: String },
+    /// Picks up a session that lost its connection, using the token from `LoggedIn`.
+    Resume { token: String },
+    Chat { text: String },
+    /// Talks to the opponent and the spectators of the game the player is in.
+    GameChat { text: String },
+    Dm { to: String, text: String },
+    /// `variant` names the game to play, the server's default game if it's left out.
+    /// `difficulty` only applies to challenges to the built-in bot, `BOT_NAME`.
+    /// `position` starts the game from a position string instead of the empty board,
+    /// for m,n,k games only. `color` is the side the challenger plays, X if it's left out.
+    /// Games are rated unless `rated` says otherwise, except games from a position and
+    /// against the bot, which can't be rated.
+    Challenge {
+        opponent: String,
+        variant: Option<String>,
+        time_control: Option<TimeControl>,
+        difficulty: Option<Difficulty>,
+        position: Option<String>,
+        color: Option<ColorChoice>,
+        rated: Option<bool>,
+    },
+    Accept { opponent: String },
+    /// Turns down the challenge `opponent` sent.
+    Decline { opponent: String },
+    /// Takes back the challenge sent to `opponent`.
+    Withdraw { opponent: String },
+    /// Lists the challenges the player has received and sent that are still open.
+    Challenges,
+    /// Waits for the matchmaker to pair the player with someone seeking the same game.
+    Seek {
+        variant: Option<String>,
+        time_control: Option<TimeControl>,
+        #[serde(default)]
+        rated: bool,
+    },
+    CancelSeek,
+    Move { square: String },
+    Resign,
+    Online,
+    /// Lists the games being played.
+    Games,
+    /// Follows the game `player` is playing without taking part in it.
+    Watch { player: String },
+    Unwatch,
+    /// Lists the finished games of `player`, or of whoever asks if it's left out.
+    History { player: Option<String> },
+    /// Fetches a finished game from the archive.
+    Show { id: u64 },
+    /// Shows the rating and results of `player`, or of whoever asks if it's left out.
+    Stats { player: Option<String> },
+    /// The highest rated players, `limit` of them or the server's default number.
+    Leaderboard { limit: Option<usize> },
+    /// How the rating of `player` changed game by game.
+    RatingHistory { player: String },
+    /// Fetches a finished game from the archive written in the notation of `write_notation`.
+    Export { id: u64 },
+    /// Goes through a finished game position by position. With `interval_ms` the positions
+    /// are sent one after another at that pace, otherwise the client steps with `ReplayNext`
+    /// and `ReplayPrev`.
+    Replay { id: u64, interval_ms: Option<u64> },
+    ReplayNext,
+    ReplayPrev,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Usage,
+    UnsupportedVersion,
+    AuthFailed,
+    AlreadyLoggedIn,
+    RegistrationFailed,
+    NotOnline,
+    Busy,
+    NoChallenge,
+    NotYourTurn,
+    IllegalMove,
+    Unavailable,
+    UnknownVariant,
+    NotPlaying,
+    UnknownGame,
+    NoReplay,
+    InvalidPosition,
+    NotSeeking,
+}
+
+/// Direct replies to a `Request` from the same connection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Hello { version: u32 },
+    LoggedIn { username: String, token: String },
+    Resumed { username: String, token: String },
+    DmSent { to: String },
+    ChallengeSent { to: String },
+    ChallengeAccepted { opponent: String },
+    ChallengeDeclined { from: String },
+    ChallengeWithdrawn { to: String },
+    Challenges { incoming: Vec<PendingChallenge>, outgoing: Vec<PendingChallenge> },
+    Seeking { variant: String, time_control: Option<TimeControl>, rated: bool },
+    SeekCancelled,
+    Online { players: Vec<String> },
+    Games { games: Vec<LiveGame> },
+    Watching { x: String, o: String },
+    Unwatched,
+    History { player: String, games: Vec<GameSummary> },
+    Game { game: GameRecord },
+    Export { id: u64, notation: String },
+    Stats { player: String, stats: PlayerStats },
+    Leaderboard { players: Vec<LeaderboardEntry> },
+    RatingHistory { player: String, points: Vec<RatingPoint> },
+    Error { code: ErrorCode, message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    X,
+    O,
+    Draw,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    Line,
+    BoardFull,
+    Resignation,
+    /// The loser disconnected and didn't come back in time.
+    Forfeit,
+    /// The loser's clock ran out.
+    Timeout,
+    /// The loser was an engine that crashed, didn't answer in time or played an illegal move.
+    EngineFailure,
+}
+
+/// A game in progress, as listed by `Request::Games`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LiveGame {
+    pub variant: String,
+    pub x: String,
+    pub o: String,
+    pub spectators: usize,
+}
+
+/// A move of a finished game.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMove {
+    /// Written the way the player typed it.
+    pub mv: String,
+    /// Milliseconds since the start of the game.
+    pub at_ms: u64,
+}
+
+/// A finished game as it is kept in the archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub id: u64,
+    pub variant: String,
+    pub x: String,
+    pub o: String,
+    pub time_control: Option<TimeControl>,
+    /// Whether the game counted towards the players' ratings.
+    #[serde(default)]
+    pub rated: bool,
+    /// The position string the game started from, if not the empty board.
+    pub start_position: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub outcome: Outcome,
+    pub reason: EndReason,
+    pub moves: Vec<RecordedMove>,
+}
+
+/// A finished game without its moves, as listed by `Request::History`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameSummary {
+    pub id: u64,
+    pub variant: String,
+    pub x: String,
+    pub o: String,
+    pub started_at: u64,
+    pub outcome: Outcome,
+    pub reason: EndReason,
+    pub move_count: usize,
+}
+
+impl GameRecord {
+    /// Which side made the first move. Position strings end with the side to move.
+    pub fn first_to_move(&self) -> Outcome {
+        match &self.start_position {
+            Some(position) if position.ends_with(":o") => Outcome::O,
+            _ => Outcome::X,
+        }
+    }
+
+    pub fn summary(&self) -> GameSummary {
+        GameSummary {
+            id: self.id,
+            variant: self.variant.clone(),
+            x: self.x.clone(),
+            o: self.o.clone(),
+            started_at: self.started_at,
+            outcome: self.outcome,
+            reason: self.reason,
+            move_count: self.moves.len(),
+        }
+    }
+}
+
+/// A challenge that hasn't been answered yet, as listed by `Response::Challenges`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PendingChallenge {
+    /// Who sent it for incoming challenges, who it went to for outgoing ones.
+    pub player: String,
+    pub variant: String,
+    pub time_control: Option<TimeControl>,
+    pub position: Option<String>,
+    pub rated: bool,
+    /// The side the challenger plays.
+    pub color: ColorChoice,
+    /// Seconds until the challenge expires.
+    pub expires_in: u64,
+}
+
+/// Why a challenge was closed without a game.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeEnd {
+    Declined,
+    Withdrawn,
+    Expired,
+    /// The challenger's session ended.
+    ChallengerLeft,
+    /// The challenged player's session ended.
+    ChallengedLeft,
+}
+
+/// A player's rating and how their games went.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerStats {
+    /// Elo, only rated games count.
+    pub rating: i32,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// Losses by resigning, counted in `losses` too.
+    pub resignations: u32,
+}
+
+/// A line of `Response::Leaderboard`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeaderboardEntry {
+    pub username: String,
+    pub rating: i32,
+    pub rated_games: u32,
+}
+
+/// A player's rating right after a rated game.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatingPoint {
+    /// The id of the game in the archive.
+    pub game: u64,
+    /// When the game finished, in seconds since the Unix epoch.
+    pub at: u64,
+    pub rating: i32,
+}
+
+/// Time left on each player's clock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    pub x_ms: u64,
+    pub o_ms: u64,
+}
+
+/// Messages the server pushes without the client asking for them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Chat { from: String, text: String },
+    GameChat { from: String, text: String },
+    DirectMessage { from: String, text: String },
+    /// `color` is the side `from` plays.
+    ChallengeReceived {
+        from: String,
+        variant: String,
+        time_control: Option<TimeControl>,
+        position: Option<String>,
+        rated: bool,
+        color: ColorChoice,
+    },
+    ChallengeAccepted { by: String },
+    /// The challenge `from` sent to `to` is off, sent to both of them.
+    ChallengeClosed { from: String, to: String, reason: ChallengeEnd },
+    /// A player with a pending challenge to or from you lost their connection. The challenge
+    /// stays open, but can't be accepted until they are back.
+    ChallengePartyOffline { player: String },
+    /// The matchmaker found an opponent, the game starts right away.
+    Matched { opponent: String, opponent_rating: i32 },
+    /// The account logged in from another connection, which replaces this one.
+    SessionTakenOver,
+    GameRecovered,
+    /// The opponent lost their connection and forfeits when the countdown runs out.
+    ForfeitCountdown { player: String, seconds_left: u64 },
+    OpponentReconnected { player: String },
+    BoardUpdate {
+        /// Which game is being played, e.g. "tictactoe" or "connect4".
+        variant: String,
+        x: String,
+        o: String,
+        /// Rows of the board, each cell rendered as "_", "X" or "O". Ultimate Tic Tac Toe
+        /// also has "|" and "-" cells between its sub-boards.
+        board: Vec<Vec<String>>,
+        /// Only set for games with a time control.
+        clocks: Option<Clocks>,
+        /// `None` once the game is over.
+        to_move: Option<String>,
+        /// How to enter a move in this game.
+        move_hint: String,
+        your_turn: bool,
+        /// Set for updates sent to spectators rather than the players.
+        spectating: bool,
+    },
+    /// Sent before the `BoardUpdate` of every position of a replay. `ply` counts the moves
+    /// played so far, `outcome` and `reason` are only set on the last position.
+    ReplayStep {
+        id: u64,
+        ply: usize,
+        total: usize,
+        last_move: Option<String>,
+        /// Who played `last_move`.
+        last_side: Option<Outcome>,
+        outcome: Option<Outcome>,
+        reason: Option<EndReason>,
+    },
+    GameOver {
+        outcome: Outcome,
+        reason: EndReason,
+        winner: Option<String>,
+        loser: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Response(Response),
+    Event(Event),
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: &str) -> ServerMessage {
+        ServerMessage::Response(Response::Error { code, message: message.to_string() })
+    }
+}
+
+/// How a connection wants its messages encoded. Clients that open with a `Hello`
+/// request get JSON, everyone else keeps the original plain text interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Text,
+    Json,
+}
+
+impl Mode {
+    pub fn encode(&self, message: &ServerMessage) -> String {
+        match self {
+            Mode::Text => message.to_string(),
+            Mode::Json => serde_json::to_string(message).unwrap(),
+        }
+    }
+}
+
+pub fn parse_json_request(message: &str) -> Option<Request> {
+    serde_json::from_str(message).ok()
+}
+
+
+pub fn encode_request(request: &Request) -> String {
+    serde_json::to_string(request).unwrap()
+}
+
+pub fn parse_json_message(message: &str) -> Option<ServerMessage> {
+    serde_json::from_str(message).ok()
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+
+use crate::message::*;
+
+/// Why a game in notation couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError(pub String);
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Writes a finished game as text, a tag per line followed by the moves:
+///
+/// ```text
+/// [Id "12"]
+/// [Variant "tictactoe"]
+/// [X "alice"]
+/// [O "bob"]
+/// [TimeControl "60+2"]
+/// [Rated "yes"]
+/// [Started "1760000000"]
+/// [Result "1-0"]
+/// [Termination "line"]
+///
+/// 1. 5 {1.250} 1 {3.004} 2. 9 {4.100} 3 {6.020} 3. 7 {7.001} 1-0
+/// ```
+///
+/// Moves are written the way players type them, each followed by the seconds since the
+/// start of the game when it was played. `TimeControl` is `-` for untimed games, and
+/// `Position` is only there for games that didn't start from the empty board.
+pub fn write_notation(game: &GameRecord) -> String {
+    let mut text = String::new();
+    let time_control = game.time_control.map_or("-".to_string(), |tc| tc.to_string());
+    let mut tags = vec![
+        ("Id", game.id.to_string()),
+        ("Variant", game.variant.clone()),
+        ("X", game.x.clone()),
+        ("O", game.o.clone()),
+        ("TimeControl", time_control),
+        ("Rated", if game.rated { "yes" } else { "no" }.to_string()),
+    ];
+    if let Some(position) = &game.start_position {
+        tags.push(("Position", position.clone()));
+    }
+    tags.extend([
+        ("Started", game.started_at.to_string()),
+        ("Result", result_name(game.outcome).to_string()),
+        ("Termination", reason_name(game.reason).to_string()),
+    ]);
+    for (name, value) in tags {
+        text.push_str(&format!("[{} \"{}\"]\n", name, value));
+    }
+    text.push('\n');
+    let mut movetext = vec![];
+    for (i, played) in game.moves.iter().enumerate() {
+        if i % 2 == 0 {
+            movetext.push(format!("{}.", i / 2 + 1));
+        }
+        movetext.push(played.mv.clone());
+        movetext.push(format!("{{{}.{:03}}}", played.at_ms / 1000, played.at_ms % 1000));
+    }
+    movetext.push(result_name(game.outcome).to_string());
+    text.push_str(&movetext.join(" "));
+    text.push('\n');
+    text
+}
+
+/// Reads a game written by `write_notation`. Unknown tags are skipped, `Id`, `Started` and
+/// `Rated`, which defaults to `no`, can be left out and so can the times of the moves. The
+/// moves aren't checked against the rules of the game, that's up to whoever plays them.
+pub fn read_notation(text: &str) -> Result<GameRecord, NotationError> {
+    let error = |message: String| Err(NotationError(message));
+    let mut id = None;
+    let mut variant = None;
+    let mut x = None;
+    let mut o = None;
+    let mut time_control = None;
+    let mut rated = false;
+    let mut start_position = None;
+    let mut started_at = None;
+    let mut outcome = None;
+    let mut reason = None;
+    let mut moves: Vec<RecordedMove> = vec![];
+    let mut finished = false;
+
+    for line in text.lines().map(str::trim) {
+        if let Some(tag) = line.strip_prefix('[') {
+            let Some((name, value)) = tag.strip_suffix(']').and_then(|tag| tag.split_once(' ')) else {
+                return error(format!("malformed tag: {}", line));
+            };
+            let Some(value) = value.trim().strip_prefix('"').and_then(|value| value.strip_suffix('"')) else {
+                return error(format!("tag values go in quotes: {}", line));
+            };
+            match name {
+                "Id" => id = Some(value.parse().map_err(|_| NotationError(format!("bad game id: {}", value)))?),
+                "Variant" => variant = Some(value.to_string()),
+                "X" => x = Some(value.to_string()),
+                "O" => o = Some(value.to_string()),
+                "TimeControl" if value == "-" => time_control = None,
+                "TimeControl" => time_control = Some(value.parse().map_err(|_| NotationError(format!("bad time control: {}", value)))?),
+                "Rated" => rated = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return error(format!("rated is yes or no, not {}", value)),
+                },
+                "Position" => start_position = Some(value.to_string()),
+                "Started" => started_at = Some(value.parse().map_err(|_| NotationError(format!("bad start time: {}", value)))?),
+                "Result" => outcome = Some(parse_result(value).ok_or_else(|| NotationError(format!("bad result: {}", value)))?),
+                "Termination" => reason = Some(parse_reason(value).ok_or_else(|| NotationError(format!("bad termination: {}", value)))?),
+                _ => {}
+            }
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if finished {
+                return error(format!("moves after the result: {}", token));
+            }
+            if let Some(time) = token.strip_prefix('{').and_then(|time| time.strip_suffix('}')) {
+                let Some(played) = moves.last_mut() else {
+                    return error(format!("time before the first move: {}", token));
+                };
+                played.at_ms = parse_seconds(time).ok_or_else(|| NotationError(format!("bad move time: {}", token)))?;
+            }
+            else if token.strip_suffix('.').is_some_and(|number| number.parse::<u64>().is_ok()) {
+                continue;
+            }
+            else if let Some(result) = parse_result(token) {
+                if outcome.is_some_and(|outcome| outcome != result) {
+                    return error(format!("the moves end in {} but the result tag says otherwise", token));
+                }
+                outcome = Some(result);
+                finished = true;
+            }
+            else {
+                moves.push(RecordedMove { mv: token.to_string(), at_ms: 0 });
+            }
+        }
+    }
+
+    let missing = |tag: &str| NotationError(format!("missing {} tag", tag));
+    Ok(GameRecord {
+        id: id.unwrap_or(0),
+        variant: variant.ok_or_else(|| missing("Variant"))?,
+        x: x.ok_or_else(|| missing("X"))?,
+        o: o.ok_or_else(|| missing("O"))?,
+        time_control,
+        rated,
+        start_position,
+        started_at: started_at.unwrap_or(0),
+        outcome: outcome.ok_or_else(|| missing("Result"))?,
+        reason: reason.ok_or_else(|| missing("Termination"))?,
+        moves,
+    })
+}
+
+fn result_name(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::X => "1-0",
+        Outcome::O => "0-1",
+        Outcome::Draw => "1/2-1/2",
+    }
+}
+
+fn parse_result(s: &str) -> Option<Outcome> {
+    match s {
+        "1-0" => Some(Outcome::X),
+        "0-1" => Some(Outcome::O),
+        "1/2-1/2" => Some(Outcome::Draw),
+        _ => None,
+    }
+}
+
+fn reason_name(reason: EndReason) -> &'static str {
+    match reason {
+        EndReason::Line => "line",
+        EndReason::BoardFull => "board_full",
+        EndReason::Resignation => "resignation",
+        EndReason::Forfeit => "forfeit",
+        EndReason::Timeout => "timeout",
+        EndReason::EngineFailure => "engine_failure",
+    }
+}
+
+fn parse_reason(s: &str) -> Option<EndReason> {
+    match s {
+        "line" => Some(EndReason::Line),
+        "board_full" => Some(EndReason::BoardFull),
+        "resignation" => Some(EndReason::Resignation),
+        "forfeit" => Some(EndReason::Forfeit),
+        "timeout" => Some(EndReason::Timeout),
+        "engine_failure" => Some(EndReason::EngineFailure),
+        _ => None,
+    }
+}
+
+/// Seconds with up to three decimals, as written after every move.
+fn parse_seconds(s: &str) -> Option<u64> {
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+    seconds.parse::<u64>().ok()?.checked_mul(1000)?.checked_add(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_hand_written_games() {
+        let text = "[Variant \"connect4\"]\n[X \"alice\"]\n[O \"bob\"]\n[Event \"club night\"]\n[Result \"0-1\"]\n[Termination \"resignation\"]\n\n1. 4 4 {2.5}\n2. 3 0-1\n";
+        let game = read_notation(text).unwrap();
+        assert_eq!(game.id, 0);
+        assert_eq!(game.time_control, None);
+        assert_eq!(game.outcome, Outcome::O);
+        assert_eq!(game.moves, vec![
+            RecordedMove { mv: "4".to_string(), at_ms: 0 },
+            RecordedMove { mv: "4".to_string(), at_ms: 2500 },
+            RecordedMove { mv: "3".to_string(), at_ms: 0 },
+        ]);
+    }
+
+    #[test]
+    fn rejects_broken_games() {
+        let tags = "[Variant \"tictactoe\"]\n[X \"alice\"]\n[O \"bob\"]\n[Result \"1-0\"]\n[Termination \"line\"]\n";
+        assert!(read_notation(&format!("{}\n1. 5 0-1", tags)).is_err());
+        assert!(read_notation(&format!("{}\n1. 5 1-0 2", tags)).is_err());
+        assert!(read_notation(&format!("{}\n{{1.000}} 5", tags)).is_err());
+        assert!(read_notation(&format!("{}\n1. 5 {{1.2345}}", tags)).is_err());
+        assert!(read_notation(&format!("{}\n1. 5 {{99999999999999999.0}}", tags)).is_err());
+        assert!(read_notation(&format!("{}[TimeControl \"fast\"]\n", tags)).is_err());
+        assert!(read_notation("[X \"alice\"]\n[O \"bob\"]\n[Result \"1-0\"]\n[Termination \"line\"]\n").is_err());
+    }
+}
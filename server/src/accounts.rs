@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::storage::*;
+
+const SALT_LEN: usize = 16;
+const TOKEN_LEN: usize = 16;
+const HASH_ROUNDS: usize = 10_000;
+const MAX_USERNAME_LEN: usize = 20;
+const MIN_PASSWORD_LEN: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Account {
+    salt: String,
+    hash: String,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidUsername,
+    WeakPassword,
+    UsernameTaken,
+    /// Unknown user or wrong password, deliberately not telling which.
+    BadCredentials,
+    Io(io::Error),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::InvalidUsername => write!(f, "usernames are 1-{} letters, digits, '-' or '_'", MAX_USERNAME_LEN),
+            AccountError::WeakPassword => write!(f, "passwords must be at least {} characters", MIN_PASSWORD_LEN),
+            AccountError::UsernameTaken => write!(f, "that username is already registered"),
+            AccountError::BadCredentials => write!(f, "wrong username or password"),
+            AccountError::Io(e) => write!(f, "could not save account: {}", e),
+        }
+    }
+}
+
+/// Registered accounts, kept in a JSON file.
+pub struct AccountStore {
+    path: PathBuf,
+    accounts: HashMap<String, Account>,
+    /// Names the server uses for players that aren't accounts, like the bots.
+    reserved: Vec<String>,
+}
+
+impl AccountStore {
+    pub fn load(path: &Path) -> io::Result<AccountStore> {
+        Ok(AccountStore {
+            path: path.to_path_buf(),
+            accounts: load_json(path)?,
+            reserved: vec![protocol::BOT_NAME.to_string()],
+        })
+    }
+
+    /// Keeps `username` from being registered, failing if someone already has.
+    pub fn reserve(&mut self, username: &str) -> Result<(), AccountError> {
+        if self.is_taken(username) {
+            return Err(AccountError::UsernameTaken);
+        }
+        self.reserved.push(username.to_string());
+        Ok(())
+    }
+
+    fn is_taken(&self, username: &str) -> bool {
+        self.accounts.contains_key(username) || self.reserved.iter().any(|name| name.eq_ignore_ascii_case(username))
+    }
+
+    pub fn register(&mut self, username: &str, password: &str) -> Result<(), AccountError> {
+        if !is_valid_username(username) {
+            return Err(AccountError::InvalidUsername);
+        }
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AccountError::WeakPassword);
+        }
+        if self.is_taken(username) {
+            return Err(AccountError::UsernameTaken);
+        }
+        let mut salt = [0; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let account = Account {
+            salt: to_hex(&salt),
+            hash: hash_password(&salt, password),
+        };
+        self.accounts.insert(username.to_string(), account);
+        self.save().map_err(AccountError::Io)
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> Result<(), AccountError> {
+        let account = self.accounts.get(username).ok_or(AccountError::BadCredentials)?;
+        let salt = from_hex(&account.salt).ok_or(AccountError::BadCredentials)?;
+        if hash_password(&salt, password) == account.hash {
+            Ok(())
+        }
+        else {
+            Err(AccountError::BadCredentials)
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_json(&self.path, &self.accounts)
+    }
+}
+
+fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LEN
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// An unguessable token, used to let a client resume its session without the password.
+pub fn random_token() -> String {
+    let mut token = [0; TOKEN_LEN];
+    rand::thread_rng().fill_bytes(&mut token);
+    to_hex(&token)
+}
+
+fn hash_password(salt: &[u8], password: &str) -> String {
+    let mut hash = Sha256::new()
+        .chain_update(salt)
+        .chain_update(password.as_bytes())
+        .finalize();
+    for _ in 1..HASH_ROUNDS {
+        hash = Sha256::new()
+            .chain_update(salt)
+            .chain_update(hash)
+            .finalize();
+    }
+    to_hex(&hash)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
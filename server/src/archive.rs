@@ -0,0 +1,49 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use protocol::*;
+
+use crate::ratings::*;
+use crate::storage::*;
+
+/// Finished games, kept in a JSON file and numbered from 1 in the order they finished.
+pub struct Archive {
+    path: PathBuf,
+    games: Vec<GameRecord>,
+    /// What the games add up to for every player, kept in a file of its own.
+    pub ratings: Ratings,
+}
+
+impl Archive {
+    pub fn load(path: &Path, ratings_path: &Path) -> io::Result<Archive> {
+        Ok(Archive { path: path.to_path_buf(), games: load_json(path)?, ratings: Ratings::load(ratings_path)? })
+    }
+
+    pub fn next_id(&self) -> u64 {
+        self.games.len() as u64 + 1
+    }
+
+    pub fn add(&mut self, record: GameRecord) -> io::Result<()> {
+        self.ratings.add(&record)?;
+        self.games.push(record);
+        save_json(&self.path, &self.games)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&GameRecord> {
+        let index = id.checked_sub(1)? as usize;
+        self.games.get(index)
+    }
+
+    /// The last `limit` games `username` played, newest first.
+    pub fn history(&self, username: &str, limit: usize) -> Vec<GameSummary> {
+        self.games
+            .iter()
+            .rev()
+            .filter(|game| game.x == username || game.o == username)
+            .take(limit)
+            .map(|game| game.summary())
+            .collect()
+    }
+}
@@ -0,0 +1,134 @@
+use rand::seq::SliceRandom;
+
+use protocol::*;
+
+use crate::game::*;
+
+/// Scores are from the point of view of the side to move. Wins are worth less the longer
+/// they take, so the bot goes for the quickest win and the slowest loss.
+const WIN: i32 = 1_000;
+const INFINITY: i32 = WIN + 1;
+/// How far the medium bot looks ahead, enough to take a win and block one.
+const MEDIUM_DEPTH: u32 = 2;
+/// Positions the perfect bot may visit before it settles for the deepest search it finished.
+const NODE_BUDGET: usize = 100_000;
+
+/// Picks a move for `side`, `None` if there is nothing to play.
+pub fn choose_move(rules: &dyn GameRules, side: State, difficulty: Difficulty) -> Option<usize> {
+    let mut moves = rules.legal_moves();
+    // shuffled so that the bot picks at random between moves that are equally good
+    moves.shuffle(&mut rand::thread_rng());
+    match difficulty {
+        Difficulty::Easy => moves.first().copied(),
+        Difficulty::Medium => Search::new().best_move(rules, &moves, side, MEDIUM_DEPTH),
+        Difficulty::Perfect => {
+            let mut best = None;
+            for depth in 1.. {
+                let mut search = Search::new();
+                match search.best_move(rules, &moves, side, depth) {
+                    Some(mv) => best = Some(mv),
+                    None => break,
+                }
+                if !search.cut_off {
+                    break;
+                }
+            }
+            best.or(moves.first().copied())
+        }
+    }
+}
+
+/// A depth limited negamax search with alpha-beta pruning.
+struct Search {
+    nodes: usize,
+    /// Whether the depth limit was hit anywhere, if not the result is exact.
+    cut_off: bool,
+}
+
+impl Search {
+    fn new() -> Search {
+        Search { nodes: 0, cut_off: false }
+    }
+
+    /// `None` if the node budget ran out before the search finished.
+    fn best_move(&mut self, rules: &dyn GameRules, moves: &[usize], side: State, depth: u32) -> Option<usize> {
+        let mut best = None;
+        let mut alpha = -INFINITY;
+        for &mv in moves {
+            let mut child = rules.clone_box();
+            child.apply(mv, side);
+            let score = -self.negamax(child.as_ref(), side.opponent(), depth - 1, 1, -INFINITY, -alpha)?;
+            if best.is_none() || score > alpha {
+                alpha = score;
+                best = Some(mv);
+            }
+        }
+        best
+    }
+
+    fn negamax(&mut self, rules: &dyn GameRules, side: State, depth: u32, ply: i32, mut alpha: i32, beta: i32) -> Option<i32> {
+        self.nodes += 1;
+        if self.nodes > NODE_BUDGET {
+            return None;
+        }
+        match rules.outcome() {
+            State::None => {}
+            State::Draw => return Some(0),
+            winner if winner == side => return Some(WIN - ply),
+            _ => return Some(ply - WIN),
+        }
+        if depth == 0 {
+            self.cut_off = true;
+            return Some(0);
+        }
+        let mut best = -INFINITY;
+        for mv in rules.legal_moves() {
+            let mut child = rules.clone_box();
+            child.apply(mv, side);
+            let score = -self.negamax(child.as_ref(), side.opponent(), depth - 1, ply + 1, -beta, -alpha)?;
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnk::MnkBoard;
+
+    /// Plays a game of Tic Tac Toe between two difficulties and returns the result.
+    fn play(x: Difficulty, o: Difficulty) -> State {
+        let mut board = MnkBoard::initial();
+        let mut side = State::X;
+        while board.outcome() == State::None {
+            let difficulty = if side == State::X { x } else { o };
+            let mv = choose_move(&board, side, difficulty).unwrap();
+            board.apply(mv, side);
+            side = side.opponent();
+        }
+        board.outcome()
+    }
+
+    #[test]
+    fn perfect_never_loses_to_easy() {
+        for _ in 0..50 {
+            assert_ne!(play(Difficulty::Perfect, Difficulty::Easy), State::O);
+            assert_ne!(play(Difficulty::Easy, Difficulty::Perfect), State::X);
+        }
+    }
+
+    #[test]
+    fn medium_takes_a_win_and_blocks_one() {
+        for _ in 0..10 {
+            let (board, side) = MnkBoard::from_position(3, 3, 3, "xx1/o2/1o1:x").unwrap();
+            assert_eq!(choose_move(&board, side, Difficulty::Medium), Some(2));
+            let (board, side) = MnkBoard::from_position(3, 3, 3, "xx1/o2/3:o").unwrap();
+            assert_eq!(choose_move(&board, side, Difficulty::Medium), Some(2));
+        }
+    }
+}
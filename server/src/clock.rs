@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+use protocol::*;
+
+/// A chess clock for two players. Index 0 is X (player1), index 1 is O (player2).
+#[derive(Debug, Clone)]
+pub struct Clock {
+    increment: Duration,
+    remaining: [Duration; 2],
+    to_move: usize,
+    /// When the player to move started thinking, `None` once the clock is stopped.
+    running_since: Option<Instant>,
+}
+
+impl Clock {
+    /// Starts right away with `to_move` to move, which is 0 for X.
+    pub fn new(time_control: TimeControl, to_move: usize) -> Clock {
+        let base = Duration::from_secs(time_control.base_secs);
+        Clock {
+            increment: Duration::from_secs(time_control.increment_secs),
+            remaining: [base, base],
+            to_move,
+            running_since: Some(Instant::now()),
+        }
+    }
+
+    pub fn remaining(&self, side: usize) -> Duration {
+        match self.running_since {
+            Some(since) if side == self.to_move => self.remaining[side].saturating_sub(since.elapsed()),
+            _ => self.remaining[side],
+        }
+    }
+
+    /// The player to move finished their move: charge them the time it took, give them the
+    /// increment and start the opponent's clock.
+    pub fn punch(&mut self) {
+        let side = self.to_move;
+        self.remaining[side] = self.remaining(side) + self.increment;
+        self.to_move = 1 - side;
+        if self.running_since.is_some() {
+            self.running_since = Some(Instant::now());
+        }
+    }
+
+    pub fn stop(&mut self) {
+        let side = self.to_move;
+        self.remaining[side] = self.remaining(side);
+        self.running_since = None;
+    }
+
+    /// The side whose time has run out, if any.
+    pub fn flagged(&self) -> Option<usize> {
+        if self.remaining(self.to_move).is_zero() { Some(self.to_move) } else { None }
+    }
+
+    pub fn clocks(&self) -> Clocks {
+        Clocks {
+            x_ms: self.remaining(0).as_millis() as u64,
+            o_ms: self.remaining(1).as_millis() as u64,
+        }
+    }
+}
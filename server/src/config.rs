@@ -0,0 +1,97 @@
+use std::{fmt, time::Duration};
+
+/// What happens when someone logs in to an account that already has a live session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionPolicy {
+    /// Keep the existing session and turn the new connection away.
+    Reject,
+    /// Close the existing session and hand its state over to the new connection.
+    Takeover,
+}
+
+/// An external bot program the server runs and lets people challenge under `name`.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub name: String,
+    pub command: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub session_policy: SessionPolicy,
+    /// How long a disconnected session is kept around for the client to resume it.
+    pub session_grace: Duration,
+    /// How long a player can be disconnected from a game before it counts as a forfeit.
+    pub forfeit_after: Duration,
+    pub engines: Vec<EngineConfig>,
+    /// How long an engine gets to come up with a move before it forfeits the game.
+    pub engine_timeout: Duration,
+    /// How long a challenge stays open before it expires.
+    pub challenge_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.0, USAGE)
+    }
+}
+
+const USAGE: &str = "usage: server [--session-policy reject|takeover] [--session-grace <seconds>] [--forfeit-after <seconds>] [--engine <name>=<command>]... [--engine-timeout <seconds>] [--challenge-timeout <seconds>]";
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            session_policy: SessionPolicy::Takeover,
+            session_grace: Duration::from_secs(120),
+            forfeit_after: Duration::from_secs(60),
+            engines: vec![],
+            engine_timeout: Duration::from_secs(10),
+            challenge_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| ConfigError(format!("{} needs a value", flag)));
+            match flag.as_str() {
+                "--session-policy" => {
+                    config.session_policy = match value()?.as_str() {
+                        "reject" => SessionPolicy::Reject,
+                        "takeover" => SessionPolicy::Takeover,
+                        other => return Err(ConfigError(format!("unknown session policy {}", other))),
+                    }
+                }
+                "--session-grace" => config.session_grace = seconds(&flag, &value()?)?,
+                "--forfeit-after" => config.forfeit_after = seconds(&flag, &value()?)?,
+                "--engine" => {
+                    let value = value()?;
+                    match value.split_once('=') {
+                        Some((name, command)) if !name.is_empty() && !name.contains(char::is_whitespace) && !command.trim().is_empty() => {
+                            config.engines.push(EngineConfig { name: name.to_string(), command: command.to_string() });
+                        }
+                        _ => return Err(ConfigError(format!("--engine expects <name>=<command>, got {}", value))),
+                    }
+                }
+                "--engine-timeout" => config.engine_timeout = seconds(&flag, &value()?)?,
+                "--challenge-timeout" => config.challenge_timeout = seconds(&flag, &value()?)?,
+                _ => return Err(ConfigError(format!("unknown argument {}", flag))),
+            }
+        }
+        if config.forfeit_after > config.session_grace {
+            return Err(ConfigError("--forfeit-after can't be longer than --session-grace, the game would outlast the session".to_string()));
+        }
+        Ok(config)
+    }
+}
+
+fn seconds(flag: &str, value: &str) -> Result<Duration, ConfigError> {
+    value.parse()
+    .map(Duration::from_secs)
+    .map_err(|_| ConfigError(format!("{} expects a number of seconds, got {}", flag, value)))
+}
@@ -0,0 +1,161 @@
+use crate::game::*;
+
+const COLUMNS: usize = 7;
+const ROWS: usize = 6;
+const CONNECT: usize = 4;
+
+/// Connect Four on a 7x6 board. A move is a column, numbered 1-7 from the left, and the
+/// piece drops to the lowest empty cell in it.
+#[derive(Debug, Clone)]
+pub struct ConnectFour {
+    /// Row by row from the top.
+    pub board: [State; COLUMNS * ROWS],
+    /// Set as soon as a piece completes a line, so only lines through that piece need checking.
+    winner: State,
+}
+
+impl ConnectFour {
+    fn cell(&self, row: usize, column: usize) -> State {
+        self.board[row * COLUMNS + column]
+    }
+
+    /// How many of `side`'s pieces follow `row`, `column` in one direction, not counting
+    /// that cell itself.
+    fn run(&self, row: usize, column: usize, d_row: isize, d_column: isize, side: State) -> usize {
+        let (mut r, mut c) = (row as isize, column as isize);
+        let mut count = 0;
+        loop {
+            r += d_row;
+            c += d_column;
+            if r < 0 || r >= ROWS as isize || c < 0 || c >= COLUMNS as isize || self.cell(r as usize, c as usize) != side {
+                return count;
+            }
+            count += 1;
+        }
+    }
+}
+
+impl GameRules for ConnectFour {
+    fn initial() -> ConnectFour {
+        ConnectFour { board: [State::None; COLUMNS * ROWS], winner: State::None }
+    }
+
+    fn parse_move(&self, input: &str) -> Option<usize> {
+        match input.parse::<usize>() {
+            Ok(column) if (1..=COLUMNS).contains(&column) => Some(column - 1),
+            _ => None,
+        }
+    }
+
+    fn format_move(&self, mv: usize) -> String {
+        (mv + 1).to_string()
+    }
+
+    fn move_count(&self) -> usize {
+        COLUMNS
+    }
+
+    fn is_legal(&self, mv: usize) -> bool {
+        self.outcome() == State::None && self.cell(0, mv) == State::None
+    }
+
+    fn apply(&mut self, mv: usize, side: State) {
+        if let Some(row) = (0..ROWS).rev().find(|&row| self.cell(row, mv) == State::None) {
+            self.board[row * COLUMNS + mv] = side;
+            let connects = [(0, 1), (1, 0), (1, 1), (1, -1)].iter().any(|&(d_row, d_column)| {
+                1 + self.run(row, mv, d_row, d_column, side) + self.run(row, mv, -d_row, -d_column, side) >= CONNECT
+            });
+            if connects {
+                self.winner = side;
+            }
+        }
+    }
+
+    fn outcome(&self) -> State {
+        if self.winner != State::None {
+            return self.winner;
+        }
+        if (0..COLUMNS).all(|column| self.cell(0, column) != State::None) {
+            return State::Draw;
+        }
+        State::None
+    }
+
+    fn render(&self) -> Vec<Vec<String>> {
+        self.board
+            .chunks(COLUMNS)
+            .map(|row| row.iter().map(|cell| format!("{:?}", cell)).collect())
+            .collect()
+    }
+
+    fn move_hint(&self) -> String {
+        format!("type 1-{} to drop a piece in that column", COLUMNS)
+    }
+
+    fn clone_box(&self) -> Box<dyn GameRules> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drops pieces in `columns` (numbered from 1) for `side`, returning the outcome after each.
+    fn play(board: &mut ConnectFour, side: State, columns: &[&str]) -> Vec<State> {
+        columns.iter().map(|column| {
+            let mv = board.parse_move(column).unwrap();
+            assert!(board.is_legal(mv), "column {} should be open", column);
+            board.apply(mv, side);
+            board.outcome()
+        }).collect()
+    }
+
+    #[test]
+    fn pieces_drop_to_the_lowest_free_row() {
+        let mut board = ConnectFour::initial();
+        play(&mut board, State::X, &["3"]);
+        assert_eq!(board.cell(ROWS - 1, 2), State::X);
+        play(&mut board, State::O, &["3"]);
+        assert_eq!(board.cell(ROWS - 2, 2), State::O);
+        assert_eq!(board.board.iter().filter(|&&cell| cell != State::None).count(), 2);
+    }
+
+    #[test]
+    fn a_full_column_is_illegal() {
+        let mut board = ConnectFour::initial();
+        play(&mut board, State::X, &["1", "1", "1"]);
+        play(&mut board, State::O, &["1", "1", "1"]);
+        assert_eq!(board.outcome(), State::None);
+        assert!(!board.is_legal(0));
+        assert!(board.is_legal(1));
+        assert_eq!(board.parse_move("0"), None);
+        assert_eq!(board.parse_move("8"), None);
+    }
+
+    #[test]
+    fn finds_horizontal_and_vertical_lines() {
+        let mut board = ConnectFour::initial();
+        assert_eq!(play(&mut board, State::X, &["1", "2", "4"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::X, &["3"]), [State::X]);
+
+        let mut board = ConnectFour::initial();
+        assert_eq!(play(&mut board, State::O, &["7", "7", "7"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::O, &["7"]), [State::O]);
+    }
+
+    #[test]
+    fn finds_both_diagonals() {
+        // X climbs from the bottom left to the right, O fills in underneath.
+        let mut board = ConnectFour::initial();
+        play(&mut board, State::O, &["2", "3", "3", "4", "4", "4"]);
+        assert_eq!(play(&mut board, State::X, &["1", "2", "3"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::X, &["4"]), [State::X]);
+
+        // The mirror image, with the last piece in the middle of the line.
+        let mut board = ConnectFour::initial();
+        play(&mut board, State::X, &["6", "5", "5", "4", "4", "4"]);
+        assert_eq!(play(&mut board, State::O, &["7", "4", "5"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::O, &["6"]), [State::O]);
+    }
+}
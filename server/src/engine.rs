@@ -0,0 +1,109 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// An external bot program, spoken to over its stdin and stdout one line at a time.
+///
+/// At the start of every game the engine gets `newgame <variant>`, followed by the position
+/// string the game starts from unless it starts from the empty board. Whenever it is its turn
+/// it gets `position <moves>`, every move played so far written the way a player would type
+/// it, followed by `go`, and answers with `bestmove <move>`. Anything else it prints is ignored.
+pub struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines from the engine's stdout, read on a separate thread so waiting for them can time out.
+    lines: mpsc::Receiver<String>,
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// The engine didn't answer in time.
+    Timeout,
+    /// The engine exited or closed its output.
+    Crashed,
+    Io(io::Error),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Timeout => write!(f, "engine did not answer in time"),
+            EngineError::Crashed => write!(f, "engine stopped"),
+            EngineError::Io(e) => write!(f, "could not talk to engine: {}", e),
+        }
+    }
+}
+
+impl Engine {
+    /// Starts `command`, a program followed by its arguments separated by spaces.
+    pub fn spawn(command: &str) -> io::Result<Engine> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(Engine { child, stdin, lines })
+    }
+
+    pub fn new_game(&mut self, variant: &str, position: Option<&str>) -> Result<(), EngineError> {
+        match position {
+            Some(position) => self.send(&format!("newgame {} {}", variant, position)),
+            None => self.send(&format!("newgame {}", variant)),
+        }
+    }
+
+    /// Asks for a move in the position reached by `moves`.
+    pub fn best_move(&mut self, moves: &[String], timeout: Duration) -> Result<String, EngineError> {
+        while self.lines.try_recv().is_ok() {} //whatever is left over from earlier
+        self.send(&format!("position {}", moves.join(" ")))?;
+        self.send("go")?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) => {
+                    if let Some(("bestmove", mv)) = line.trim().split_once(' ') {
+                        return Ok(mv.trim().to_string());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(EngineError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(EngineError::Crashed),
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), EngineError> {
+        writeln!(self.stdin, "{}", line)
+        .and_then(|_| self.stdin.flush())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::BrokenPipe => EngineError::Crashed,
+            _ => EngineError::Io(e),
+        })
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,725 @@
+use std::{sync::mpsc, fmt, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use protocol::*;
+
+use crate::clock::*;
+use crate::connect_four::*;
+use crate::mnk::*;
+use crate::ultimate::*;
+
+/// Absent players get a countdown every this many seconds, and every second at the very end.
+const COUNTDOWN_STEP: u64 = 10;
+const FINAL_COUNTDOWN: u64 = 5;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum State {
+    None,
+    X,
+    O,
+    Draw,
+}
+
+impl State {
+    /// The other side, for `State::X` and `State::O`.
+    pub fn opponent(self) -> State {
+        match self {
+            State::X => State::O,
+            State::O => State::X,
+            other => other,
+        }
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state_str = match *self {
+            State::None => "_",
+            State::X => "X",
+            State::O => "O",
+            State::Draw => "Draw",
+        };
+        write!(f, "{}", state_str)
+    }
+}
+
+/// The rules of one kind of game. An implementation owns the position and nothing else,
+/// `Game` takes care of the players, whose turn it is, clocks and sending updates.
+/// Moves are numbered however suits the implementation.
+pub trait GameRules: Send + fmt::Debug {
+    /// The starting position.
+    fn initial() -> Self where Self: Sized;
+    /// Turns what a player typed into a move, `None` if it isn't one.
+    fn parse_move(&self, input: &str) -> Option<usize>;
+    /// The inverse of `parse_move`.
+    fn format_move(&self, mv: usize) -> String;
+    /// Moves are numbered from 0 up to this.
+    fn move_count(&self) -> usize;
+    fn is_legal(&self, mv: usize) -> bool;
+    fn legal_moves(&self) -> Vec<usize> {
+        (0..self.move_count()).filter(|&mv| self.is_legal(mv)).collect()
+    }
+    /// Plays a legal move for `side`, which is `State::X` or `State::O`.
+    fn apply(&mut self, mv: usize, side: State);
+    /// `State::None` while the game is still going.
+    fn outcome(&self) -> State;
+    /// The board as rows of cells.
+    fn render(&self) -> Vec<Vec<String>>;
+    /// Tells the player to move how to enter a move.
+    fn move_hint(&self) -> String;
+    fn clone_box(&self) -> Box<dyn GameRules>;
+}
+
+impl Clone for Box<dyn GameRules> {
+    fn clone(&self) -> Box<dyn GameRules> {
+        self.clone_box()
+    }
+}
+
+/// Every game the server can host. Adding a game means implementing `GameRules`
+/// and listing it here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// `k` in a row on a `columns` by `rows` board.
+    Mnk { columns: u8, rows: u8, k: u8 },
+    ConnectFour,
+    Ultimate,
+}
+
+/// m,n,k boards need a column letter for every column.
+const MAX_MNK_SIDE: u8 = 26;
+const MIN_MNK_SIDE: u8 = 3;
+
+impl Default for Variant {
+    fn default() -> Variant {
+        Variant::TIC_TAC_TOE
+    }
+}
+
+impl Variant {
+    pub const TIC_TAC_TOE: Variant = Variant::Mnk { columns: 3, rows: 3, k: 3 };
+    pub const GOMOKU: Variant = Variant::Mnk { columns: 15, rows: 15, k: 5 };
+
+    /// How the games are named in challenges, for help and error messages.
+    pub const NAMES: &'static str = "tictactoe, ultimate, connect4, gomoku or <columns>x<rows>[k<in a row>] like 4x4 or 5x5k4";
+
+    pub fn rules(&self) -> Box<dyn GameRules> {
+        match *self {
+            Variant::Mnk { columns, rows, k } => Box::new(MnkBoard::new(columns as usize, rows as usize, k as usize)),
+            Variant::ConnectFour => Box::new(ConnectFour::initial()),
+            Variant::Ultimate => Box::new(Ultimate::initial()),
+        }
+    }
+
+    /// What players type to pick the game in a challenge. The `k` of an m,n,k board
+    /// is left out when it is the length of the shorter side.
+    pub fn name(&self) -> String {
+        match *self {
+            Variant::TIC_TAC_TOE => "tictactoe".to_string(),
+            Variant::GOMOKU => "gomoku".to_string(),
+            Variant::Mnk { columns, rows, k } if k == columns.min(rows) => format!("{}x{}", columns, rows),
+            Variant::Mnk { columns, rows, k } => format!("{}x{}k{}", columns, rows, k),
+            Variant::ConnectFour => "connect4".to_string(),
+            Variant::Ultimate => "ultimate".to_string(),
+        }
+    }
+
+    /// The rules set up at `position`, a position string, and the side to move in it. Only
+    /// m,n,k games can start from a position, see `MnkBoard::from_position`.
+    pub fn start_from(&self, position: &str) -> Result<(Box<dyn GameRules>, State), String> {
+        match *self {
+            Variant::Mnk { columns, rows, k } => {
+                let (board, to_move) = MnkBoard::from_position(columns as usize, rows as usize, k as usize, position)?;
+                Ok((Box::new(board), to_move))
+            }
+            _ => Err(format!("{} can only start from the empty board", self.name())),
+        }
+    }
+
+    /// `position` written the canonical way, checked to be a position of this game.
+    pub fn normalize_position(&self, position: &str) -> Result<String, String> {
+        match *self {
+            Variant::Mnk { columns, rows, k } => {
+                let (board, to_move) = MnkBoard::from_position(columns as usize, rows as usize, k as usize, position)?;
+                Ok(board.to_position(to_move))
+            }
+            _ => Err(format!("{} can only start from the empty board", self.name())),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name.to_lowercase().as_str() {
+            "tictactoe" => Some(Variant::TIC_TAC_TOE),
+            "gomoku" => Some(Variant::GOMOKU),
+            "connect4" => Some(Variant::ConnectFour),
+            "ultimate" => Some(Variant::Ultimate),
+            other => {
+                let (size, k) = match other.split_once('k') {
+                    Some((size, k)) => (size, Some(k.parse::<u8>().ok()?)),
+                    None => (other, None),
+                };
+                let (columns, rows) = size.split_once('x')?;
+                let columns = columns.parse::<u8>().ok()?;
+                let rows = rows.parse::<u8>().ok()?;
+                let k = k.unwrap_or(columns.min(rows));
+                let sides = MIN_MNK_SIDE..=MAX_MNK_SIDE;
+                if !sides.contains(&columns) || !sides.contains(&rows) || k < MIN_MNK_SIDE || k > columns.max(rows) {
+                    return None;
+                }
+                Some(Variant::Mnk { columns, rows, k })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlayedMove {
+    pub mv: usize,
+    /// Since the start of the game.
+    pub at: Duration,
+}
+
+/// Someone following a game they aren't playing in.
+#[derive(Debug, Clone)]
+pub struct Spectator {
+    pub username: String,
+    pub channel: mpsc::Sender<ServerMessage>,
+}
+
+/// A player who lost their connection in the middle of the game.
+#[derive(Debug, Clone)]
+pub struct Absence {
+    pub player: String,
+    pub since: Instant,
+    /// The last countdown sent to the opponent, so each one goes out only once.
+    announced: u64,
+}
+
+/// Why a recorded game couldn't be set up again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    UnknownVariant(String),
+    BadPosition(String),
+    /// The move, counting from 1, isn't legal in the position before it.
+    IllegalMove { number: usize, mv: String },
+    /// The moves reach a result before the last of them.
+    MovesAfterEnd { number: usize },
+    /// The result the moves reach isn't the one on record.
+    WrongResult,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::UnknownVariant(name) => write!(f, "unknown game {}", name),
+            LoadError::BadPosition(e) => write!(f, "bad start position: {}", e),
+            LoadError::IllegalMove { number, mv } => write!(f, "move {}, {}, is not legal", number, mv),
+            LoadError::MovesAfterEnd { number } => write!(f, "the game is already over at move {}", number),
+            LoadError::WrongResult => write!(f, "the moves don't lead to the recorded result"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub variant: Variant,
+    pub rules: Box<dyn GameRules>,
+    pub player1: String,
+    pub player2: String,
+    pub player1channel: mpsc::Sender<ServerMessage>,
+    pub player2channel: mpsc::Sender<ServerMessage>,
+    pub turn: String,
+    pub time_control: Option<TimeControl>,
+    /// Set for games that don't start from the empty board, as a position string.
+    pub start_position: Option<String>,
+    /// Whether the result counts towards the players' ratings. Games from a custom
+    /// position aren't rated.
+    pub rated: bool,
+    pub started_at: SystemTime,
+    /// Every move played so far, in order.
+    pub moves: Vec<PlayedMove>,
+    pub win: State,
+    /// How the game ended, once it has.
+    pub end_reason: Option<EndReason>,
+    pub absences: Vec<Absence>,
+    /// Only games with a time control have a clock.
+    pub clock: Option<Clock>,
+    pub spectators: Vec<Spectator>,
+}
+
+impl Game {
+
+    /// `start_position` has to be a valid position of `variant`, checked when the game was
+    /// challenged.
+    pub fn new(variant: Variant, player1: String, player2: String, channel1: mpsc::Sender<ServerMessage>, channel2: mpsc::Sender<ServerMessage>, time_control: Option<TimeControl>, start_position: Option<String>) -> Game {
+        let (rules, to_move) = start(variant, start_position.as_deref());
+        let turn = if to_move == State::O { player2.clone() } else { player1.clone() };
+        Game {
+            variant,
+            rules,
+            player1,
+            player2,
+            player1channel: channel1,
+            player2channel: channel2,
+            turn,
+            time_control,
+            rated: start_position.is_none(),
+            start_position,
+            started_at: SystemTime::now(),
+            moves: vec![],
+            win: State::None,
+            end_reason: None,
+            absences: vec![],
+            clock: time_control.map(|tc| Clock::new(tc, if to_move == State::O { 1 } else { 0 })),
+            spectators: vec![],
+        }     
+    }
+
+    /// Plays `moves` through `play_move` as if the players had sent them, keeping their
+    /// recorded times. Used to set up a game again from its record, the clock, if any, isn't
+    /// wound back.
+    pub fn play_recorded(&mut self, moves: &[RecordedMove]) -> Result<(), LoadError> {
+        for (i, recorded) in moves.iter().enumerate() {
+            if self.rules.outcome() != State::None {
+                return Err(LoadError::MovesAfterEnd { number: i + 1 });
+            }
+            // checked here so that play_move doesn't send an error to the players
+            if !self.rules.parse_move(&recorded.mv).is_some_and(|mv| self.rules.is_legal(mv)) {
+                return Err(LoadError::IllegalMove { number: i + 1, mv: recorded.mv.clone() });
+            }
+            let turn = self.turn.clone();
+            let played = self.moves.len();
+            self.play_move(&turn, &recorded.mv);
+            self.moves[played].at = Duration::from_millis(recorded.at_ms);
+        }
+        Ok(())
+    }
+
+    /// Sets up a finished game from the archive, with its result. Nothing is sent to the
+    /// channels, so they can be dummies when the game is only looked at.
+    pub fn from_record(record: &GameRecord, channel1: mpsc::Sender<ServerMessage>, channel2: mpsc::Sender<ServerMessage>) -> Result<Game, LoadError> {
+        let variant = Variant::from_name(&record.variant).ok_or_else(|| LoadError::UnknownVariant(record.variant.clone()))?;
+        if let Some(position) = &record.start_position {
+            variant.start_from(position).map_err(LoadError::BadPosition)?;
+        }
+        let mut game = Game::new(variant, record.x.clone(), record.o.clone(), channel1, channel2, record.time_control, record.start_position.clone());
+        game.rated = record.rated;
+        game.play_recorded(&record.moves)?;
+        game.started_at = UNIX_EPOCH + Duration::from_secs(record.started_at);
+        let recorded = match record.outcome {
+            Outcome::X => State::X,
+            Outcome::O => State::O,
+            Outcome::Draw => State::Draw,
+        };
+        let reached = game.rules.outcome();
+        let ended_on_board = matches!(record.reason, EndReason::Line | EndReason::BoardFull);
+        if ended_on_board != (reached != State::None) || (ended_on_board && reached != recorded) {
+            return Err(LoadError::WrongResult);
+        }
+        game.win = recorded;
+        game.end_reason = Some(record.reason);
+        game.stop_clock();
+        Ok(game)
+    }
+
+    /// The board after the first `count` moves, and the side to move in it.
+    pub fn position_after(&self, count: usize) -> (Box<dyn GameRules>, State) {
+        let (mut rules, mut side) = start(self.variant, self.start_position.as_deref());
+        for played in self.moves.iter().take(count) {
+            rules.apply(played.mv, side);
+            side = side.opponent();
+        }
+        (rules, side)
+    }
+
+    pub fn play_move(&mut self,username: &str, input: &str) -> bool{
+        if self.win != State::None {
+            return false;
+        }
+        if self.turn != username {
+            let error_message = ServerMessage::error(ErrorCode::NotYourTurn, "it is not your turn");
+             match &self.turn {
+                player1_name if player1_name == &self.player1 => {
+                    let _ = self.player2channel.send(error_message);
+                },
+                player2_name if player2_name == &self.player2 => {
+                    let _ = self.player1channel.send(error_message);
+                },
+                _ => {}
+            }
+            return false;
+        }
+        match self.rules.parse_move(input) {
+            Some(mv) if self.rules.is_legal(mv) => {
+                self.moves.push(PlayedMove { mv, at: self.started_at.elapsed().unwrap_or_default() });
+                match &self.turn {
+                    player1_name if player1_name == &self.player1 => {
+                        self.rules.apply(mv, State::X);
+                        self.turn = self.player2.clone().to_string();
+                    }
+                    player2_name if player2_name == &self.player2 => {
+                        self.rules.apply(mv, State::O);
+                        self.turn = self.player1.clone().to_string();
+                    }
+                    _ => {}
+                }
+                if let Some(clock) = self.clock.as_mut() {
+                    clock.punch();
+                }
+                true
+            }
+            _ => {
+                let error_message = ServerMessage::error(ErrorCode::IllegalMove, &format!("{} is not a legal move", input));
+                self.send_to(username, error_message);
+                false
+            }
+        }
+    } 
+
+    pub fn check_for_result(&mut self) -> bool {
+        let result = self.rules.outcome();
+        if result == State::None {
+            self.send_update();
+            return false;
+        }
+        self.win = result;
+        self.end_reason = Some(if result == State::Draw { EndReason::BoardFull } else { EndReason::Line });
+        self.stop_clock();
+        self.send_update();
+        let game_over = match result {
+            State::X | State::O => {
+                let (outcome, winner, loser) = match result {
+                    State::X => (Outcome::X, &self.player1, &self.player2),
+                    _ => (Outcome::O, &self.player2, &self.player1),
+                };
+                Event::GameOver {
+                    outcome,
+                    reason: EndReason::Line,
+                    winner: Some(winner.clone()),
+                    loser: Some(loser.clone()),
+                }
+            }
+            _ => Event::GameOver {
+                outcome: Outcome::Draw,
+                reason: EndReason::BoardFull,
+                winner: None,
+                loser: None,
+            },
+        };
+        self.send_to_all(ServerMessage::Event(game_over));
+        true
+    }
+
+    pub fn opponent_of(&self, username: &str) -> &str {
+        if username == self.player1 { &self.player2 } else { &self.player1 }
+    }
+
+    pub fn send_to(&self, username: &str, message: ServerMessage) {
+        if username == self.player1 {
+            let _ = self.player1channel.send(message);
+        }
+        else if username == self.player2 {
+            let _ = self.player2channel.send(message);
+        }
+    }
+
+    /// Ends the game early with a win for the opponent of `loser`.
+    pub fn award_win(&mut self, loser: &str, reason: EndReason) {
+        let (win, outcome) = if loser == self.player1 { (State::O, Outcome::O) } else { (State::X, Outcome::X) };
+        self.win = win;
+        self.end_reason = Some(reason);
+        self.absences.clear();
+        self.stop_clock();
+        self.send_to_all(ServerMessage::Event(Event::GameOver {
+            outcome,
+            reason,
+            winner: Some(self.opponent_of(loser).to_string()),
+            loser: Some(loser.to_string()),
+        }));
+    }
+
+    /// Ends the game if the player to move has run out of time. Returns true if it did.
+    pub fn check_clock(&mut self) -> bool {
+        if self.win != State::None {
+            return false;
+        }
+        let loser = match self.clock.as_ref().and_then(|clock| clock.flagged()) {
+            Some(0) => self.player1.clone(),
+            Some(_) => self.player2.clone(),
+            None => return false,
+        };
+        self.award_win(&loser, EndReason::Timeout);
+        true
+    }
+
+    fn stop_clock(&mut self) {
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop();
+        }
+    }
+
+    pub fn player_left(&mut self, username: &str, forfeit_after: Duration) {
+        let seconds_left = forfeit_after.as_secs();
+        self.absences.push(Absence { player: username.to_string(), since: Instant::now(), announced: seconds_left });
+        self.send_to(self.opponent_of(username), ServerMessage::Event(Event::ForfeitCountdown {
+            player: username.to_string(),
+            seconds_left,
+        }));
+    }
+
+    pub fn player_returned(&mut self, username: &str) {
+        let before = self.absences.len();
+        self.absences.retain(|absence| absence.player != username);
+        if self.absences.len() != before {
+            self.send_to(self.opponent_of(username), ServerMessage::Event(Event::OpponentReconnected {
+                player: username.to_string(),
+            }));
+        }
+    }
+
+    /// Counts down for absent players and forfeits the game of anyone who has been gone
+    /// for longer than `forfeit_after`. Returns true if the game ended.
+    pub fn check_absences(&mut self, forfeit_after: Duration) -> bool {
+        if self.win != State::None {
+            return false;
+        }
+        let mut countdowns = vec![];
+        for absence in self.absences.iter_mut() {
+            let away = absence.since.elapsed();
+            if away >= forfeit_after {
+                let loser = absence.player.clone();
+                self.award_win(&loser, EndReason::Forfeit);
+                return true;
+            }
+            let seconds_left = (forfeit_after - away).as_secs() + 1;
+            if seconds_left < absence.announced && (seconds_left.is_multiple_of(COUNTDOWN_STEP) || seconds_left <= FINAL_COUNTDOWN) {
+                absence.announced = seconds_left;
+                countdowns.push((absence.player.clone(), seconds_left));
+            }
+        }
+        for (player, seconds_left) in countdowns {
+            self.send_to(self.opponent_of(&player), ServerMessage::Event(Event::ForfeitCountdown { player, seconds_left }));
+        }
+        false
+    }
+
+    /// Passes a chat message from `from` on to everyone else in the game, spectators included.
+    pub fn chat(&self, from: &str, text: &str) {
+        let message = ServerMessage::Event(Event::GameChat { from: from.to_string(), text: text.to_string() });
+        if from != self.player1 {
+            let _ = self.player1channel.send(message.clone());
+        }
+        if from != self.player2 {
+            let _ = self.player2channel.send(message.clone());
+        }
+        self.send_to_spectators(message);
+    }
+
+    /// The game as it goes into the archive, `None` while it is still being played.
+    pub fn record(&self, id: u64) -> Option<GameRecord> {
+        let outcome = match self.win {
+            State::X => Outcome::X,
+            State::O => Outcome::O,
+            State::Draw => Outcome::Draw,
+            State::None => return None,
+        };
+        Some(GameRecord {
+            id,
+            variant: self.variant.name(),
+            x: self.player1.clone(),
+            o: self.player2.clone(),
+            time_control: self.time_control,
+            rated: self.rated,
+            start_position: self.start_position.clone(),
+            started_at: self.started_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            outcome,
+            reason: self.end_reason?,
+            moves: self.moves.iter().map(|played| RecordedMove {
+                mv: self.rules.format_move(played.mv),
+                at_ms: played.at.as_millis() as u64,
+            }).collect(),
+        })
+    }
+
+    pub fn add_spectator(&mut self, username: &str, channel: mpsc::Sender<ServerMessage>) {
+        let _ = channel.send(self.update(false, true));
+        self.spectators.push(Spectator { username: username.to_string(), channel });
+    }
+
+    /// Returns true if `username` was watching.
+    pub fn remove_spectator(&mut self, username: &str) -> bool {
+        let before = self.spectators.len();
+        self.spectators.retain(|spectator| spectator.username != username);
+        self.spectators.len() != before
+    }
+
+    /// Sends `message` to the players and the spectators. A player whose session is gone
+    /// has nobody reading their channel, so failed sends are dropped like for spectators.
+    pub fn send_to_all(&self, message: ServerMessage) {
+        let _ = self.player1channel.send(message.clone());
+        let _ = self.player2channel.send(message.clone());
+        self.send_to_spectators(message);
+    }
+
+    /// Spectators can leave for good without telling the game, so failed sends are fine.
+    fn send_to_spectators(&self, message: ServerMessage) {
+        for spectator in self.spectators.iter() {
+            let _ = spectator.channel.send(message.clone());
+        }
+    }
+
+    /// Sends the board to both players and the spectators. Once the game has a result
+    /// nobody is to move.
+    pub fn send_update(&self) {
+        let _ = self.player1channel.send(self.update(self.turn == self.player1, false));
+        let _ = self.player2channel.send(self.update(self.turn == self.player2, false));
+        self.send_to_spectators(self.update(false, true));
+    }
+
+    fn update(&self, your_turn: bool, spectating: bool) -> ServerMessage {
+        let to_move = match self.win {
+            State::None => Some(self.turn.clone()),
+            _ => None,
+        };
+        ServerMessage::Event(Event::BoardUpdate {
+            variant: self.variant.name(),
+            x: self.player1.clone(),
+            o: self.player2.clone(),
+            board: self.rules.render(),
+            clocks: self.clock.as_ref().map(|clock| clock.clocks()),
+            your_turn: your_turn && to_move.is_some(),
+            to_move,
+            move_hint: self.rules.move_hint(),
+            spectating,
+        })
+    }
+}
+
+/// The starting rules of a game and the side to move first.
+fn start(variant: Variant, position: Option<&str>) -> (Box<dyn GameRules>, State) {
+    match position {
+        Some(position) => variant.start_from(position).expect("start positions are checked before games are set up"),
+        None => (variant.rules(), State::X),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `moves` alternately for alice and bob, the way the server does.
+    fn play(variant: Variant, time_control: Option<TimeControl>, moves: &[&str]) -> (Game, mpsc::Receiver<ServerMessage>) {
+        play_from(variant, time_control, None, moves)
+    }
+
+    fn play_from(variant: Variant, time_control: Option<TimeControl>, position: Option<&str>, moves: &[&str]) -> (Game, mpsc::Receiver<ServerMessage>) {
+        let (tx, rx) = mpsc::channel();
+        let position = position.map(|position| variant.normalize_position(position).unwrap());
+        let mut game = Game::new(variant, "alice".to_string(), "bob".to_string(), tx.clone(), tx, time_control, position);
+        for mv in moves {
+            let turn = game.turn.clone();
+            assert!(game.play_move(&turn, mv), "{} should be legal", mv);
+            game.check_for_result();
+        }
+        (game, rx)
+    }
+
+    /// Archives `game`, writes it out, reads it back and sets it up again.
+    fn round_trip(game: &Game) -> Game {
+        let record = game.record(7).unwrap();
+        let read = read_notation(&write_notation(&record)).unwrap();
+        assert_eq!(read, record);
+        let (tx, _rx) = mpsc::channel();
+        let loaded = Game::from_record(&read, tx.clone(), tx).unwrap();
+        assert_eq!(loaded.record(7), Some(record));
+        loaded
+    }
+
+    #[test]
+    fn round_trips_a_win_on_the_board() {
+        let (game, _rx) = play(Variant::TIC_TAC_TOE, None, &["1", "4", "2", "5", "3"]);
+        assert_eq!(game.win, State::X);
+        let loaded = round_trip(&game);
+        assert_eq!(loaded.rules.render(), game.rules.render());
+    }
+
+    #[test]
+    fn round_trips_a_draw() {
+        let (game, _rx) = play(Variant::TIC_TAC_TOE, None, &["1", "5", "9", "2", "8", "7", "3", "6", "4"]);
+        assert_eq!(game.win, State::Draw);
+        round_trip(&game);
+    }
+
+    #[test]
+    fn round_trips_a_resignation_with_a_time_control() {
+        let (mut game, _rx) = play(Variant::ConnectFour, Some(TimeControl { base_secs: 60, increment_secs: 2 }), &["4", "4", "3", "5"]);
+        game.award_win("bob", EndReason::Resignation);
+        let loaded = round_trip(&game);
+        assert_eq!(loaded.win, State::X);
+        assert_eq!(loaded.turn, "alice");
+        assert_eq!(loaded.rules.render(), game.rules.render());
+    }
+
+    #[test]
+    fn round_trips_other_variants() {
+        let (mut game, _rx) = play(Variant::Ultimate, None, &["55", "51", "15", "59"]);
+        game.award_win("alice", EndReason::Timeout);
+        round_trip(&game);
+        let (mut game, _rx) = play(Variant::GOMOKU, None, &["h8", "h9", "a1", "o15"]);
+        game.award_win("bob", EndReason::Forfeit);
+        round_trip(&game);
+    }
+
+    #[test]
+    fn round_trips_games_from_a_start_position() {
+        let (game, _rx) = play_from(Variant::TIC_TAC_TOE, Some(TimeControl { base_secs: 60, increment_secs: 0 }), Some("X2/1x1/3:o"), &["9", "2", "3", "8"]);
+        assert_eq!(game.start_position.as_deref(), Some("x2/1x1/3:o"));
+        assert_eq!(game.win, State::X);
+        let record = game.record(1).unwrap();
+        assert_eq!(record.first_to_move(), Outcome::O);
+        let loaded = round_trip(&game);
+        let (start, side) = loaded.position_after(0);
+        assert_eq!(side, State::O);
+        assert_eq!(start.render()[1], ["_", "X", "_"]);
+    }
+
+    #[test]
+    fn loaded_games_can_be_played_on() {
+        let (tx, _rx) = mpsc::channel();
+        let moves = ["1", "4", "2"].map(|mv| RecordedMove { mv: mv.to_string(), at_ms: 0 });
+        let mut game = Game::new(Variant::TIC_TAC_TOE, "alice".to_string(), "bob".to_string(), tx.clone(), tx, None, None);
+        game.play_recorded(&moves).unwrap();
+        assert_eq!(game.turn, "bob");
+        assert!(game.play_move("bob", "3"));
+        assert!(!game.check_for_result());
+    }
+
+    #[test]
+    fn rejects_records_that_dont_hold_together() {
+        let (game, _rx) = play(Variant::TIC_TAC_TOE, None, &["1", "4", "2", "5", "3"]);
+        let record = game.record(1).unwrap();
+        let load = |record: &GameRecord| {
+            let (tx, _rx) = mpsc::channel();
+            Game::from_record(record, tx.clone(), tx).map(|_| ())
+        };
+
+        let mut illegal = record.clone();
+        illegal.moves[1].mv = "1".to_string();
+        assert_eq!(load(&illegal), Err(LoadError::IllegalMove { number: 2, mv: "1".to_string() }));
+
+        let mut too_long = record.clone();
+        too_long.moves.push(RecordedMove { mv: "9".to_string(), at_ms: 0 });
+        assert_eq!(load(&too_long), Err(LoadError::MovesAfterEnd { number: 6 }));
+
+        let mut wrong_winner = record.clone();
+        wrong_winner.outcome = Outcome::O;
+        assert_eq!(load(&wrong_winner), Err(LoadError::WrongResult));
+
+        let mut unfinished = record.clone();
+        unfinished.moves.pop();
+        assert_eq!(load(&unfinished), Err(LoadError::WrongResult));
+
+        let mut unknown = record;
+        unknown.variant = "chess".to_string();
+        assert_eq!(load(&unknown), Err(LoadError::UnknownVariant("chess".to_string())));
+    }
+}
@@ -1,37 +1,130 @@
 use std::{
-    io::prelude::*,
     net::{TcpListener, TcpStream},
     thread,
     fmt::{self},
-    sync::{Arc, Mutex, mpsc::{self}}
+    sync::{Arc, Mutex, mpsc::{self}, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant}
 };
 use threadpool::ThreadPool;
 
-use crate::tic_tac_toe::*;
-mod tic_tac_toe;
+use protocol::*;
+
+use crate::accounts::*;
+use crate::archive::*;
+use crate::bot::*;
+use crate::config::*;
+use crate::engine::*;
+use crate::game::*;
+use crate::replay::*;
+use crate::session::*;
+use crate::storage::*;
+mod accounts;
+mod archive;
+mod bot;
+mod clock;
+mod config;
+mod connect_four;
+mod engine;
+mod game;
+mod mnk;
+mod ratings;
+mod replay;
+mod session;
+mod storage;
+mod ultimate;
 
 
 struct Player {
     username: String,
+    /// Tells apart successive connections of the same user.
+    session_id: u64,
+    /// Handed out at login, lets a dropped client resume this session without the password.
+    token: String,
     game: Option<usize>,
-    challenges: Vec<String>,
-    transmission_channel: mpsc::Sender<String>
+    challenges: Vec<Challenge>,
+    transmission_channel: mpsc::Sender<ServerMessage>,
+    /// `None` while the client is away and the session waits to be resumed.
+    connection: Option<Connection>,
+    disconnected_at: Option<Instant>,
+    /// Holds the messages sent while the client is away, only set while `connection` is `None`.
+    mailbox: Option<Mailbox>,
+    /// The finished game the player is stepping through, if any.
+    replay: Option<Replay>,
+    /// Set while the player waits for the matchmaker to find an opponent.
+    seek: Option<Seek>,
 }
 
 impl fmt::Debug for Player {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Player")
          .field("name", &self.username)
+         .field("session", &self.session_id)
+         .field("connected", &self.connection.is_some())
          .field("game", &self.game)
          .field("challanges", &self.challenges)
+         .field("seek", &self.seek)
          .finish()
     }
 }
 
+/// What kind of game to play, as challenged or sought.
+#[derive(Debug, Clone, PartialEq)]
+struct GameSettings {
+    variant: Variant,
+    time_control: Option<TimeControl>,
+    /// Where the game starts, if not from the empty board.
+    position: Option<String>,
+    rated: bool,
+}
+
+/// A pending invitation to play, kept by the player who received it.
+#[derive(Debug, Clone)]
+struct Challenge {
+    from: String,
+    settings: GameSettings,
+    /// The side the challenger plays.
+    color: ColorChoice,
+    /// When it was sent, it expires after `Config::challenge_timeout`.
+    sent_at: Instant,
+}
+
+/// A player's place in the matchmaking queue.
+#[derive(Debug, Clone)]
+struct Seek {
+    settings: GameSettings,
+    /// The seeker's rating when they started seeking.
+    rating: i32,
+    since: Instant,
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
 const MAX_PLAYERS: usize = 10;
 const IP_ADDRS: &str = "0.0.0.0:8080";
+const ACCOUNTS_FILE: &str = "accounts.json";
+const ARCHIVE_FILE: &str = "archive.json";
+const RATINGS_FILE: &str = "ratings.json";
+/// How many games `history` lists.
+const HISTORY_LENGTH: usize = 20;
+/// How many players `leaderboard` lists unless asked for more, and the most it lists.
+const LEADERBOARD_LENGTH: usize = 10;
+const MAX_LEADERBOARD_LENGTH: usize = 100;
+/// The longest chat message, direct message or game chat anyone can send. Passed on to
+/// others it has to fit in a frame, even with every character escaped.
+const MAX_CHAT_LENGTH: usize = 2000;
+/// How long writing to a client may block before the client counts as gone, so one that
+/// stops reading can't stall everyone else.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+/// How far apart the ratings of two seekers can be when they start seeking, and how much
+/// further apart every second either of them waits.
+const SEEK_RATING_RANGE: i32 = 100;
+const SEEK_RANGE_GROWTH: i32 = 10;
 
 fn main() {
+    let config = Config::from_args(std::env::args().skip(1))
+    .unwrap_or_else(|e| panic!("Error reading arguments: {}", e));
     let pool = ThreadPool::new(MAX_PLAYERS);
     let listener = TcpListener::bind(IP_ADDRS)
     .unwrap_or_else(|e| panic!("Error binding to port: {}", e));
@@ -40,6 +133,42 @@ fn main() {
 
     let players: Arc<Mutex<Vec<Player>>> = Arc::new(Mutex::new(vec![]));
     let games: Arc<Mutex<Vec<Game>>> = Arc::new(Mutex::new(vec![]));
+    let accounts = AccountStore::load(&data_path(ACCOUNTS_FILE))
+    .unwrap_or_else(|e| panic!("Error loading accounts: {}", e));
+    let accounts: Arc<Mutex<AccountStore>> = Arc::new(Mutex::new(accounts));
+    let archive = Archive::load(&data_path(ARCHIVE_FILE), &data_path(RATINGS_FILE))
+    .unwrap_or_else(|e| panic!("Error loading game archive: {}", e));
+    let archive: Arc<Mutex<Archive>> = Arc::new(Mutex::new(archive));
+
+    for engine_config in config.engines.iter() {
+        start_engine(engine_config, config.engine_timeout, players.clone(), games.clone(), accounts.clone(), archive.clone());
+    }
+
+    {
+        let players = players.clone();
+        let games = games.clone();
+        let archive = archive.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(MAINTENANCE_INTERVAL);
+                expire_sessions(players.clone(), config.session_grace);
+                expire_challenges(players.clone(), config.challenge_timeout);
+                adjudicate_games(players.clone(), games.clone(), archive.clone(), config.forfeit_after);
+            }
+        });
+    }
+
+    {
+        let players = players.clone();
+        let games = games.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(MATCHMAKING_INTERVAL);
+                match_seekers(players.clone(), games.clone());
+            }
+        });
+    }
 
     for stream in listener.incoming() {
         match stream {
@@ -47,7 +176,10 @@ fn main() {
                 dbg!("new connection");
                 let players = players.clone();
                 let games = games.clone();
-                pool.execute(move || handle_connection(stream, players, games));
+                let accounts = accounts.clone();
+                let archive = archive.clone();
+                let config = config.clone();
+                pool.execute(move || handle_connection(stream, players, games, accounts, archive, config));
             }
             Err(e) => {
                 println!("Error while accepting connection: {}", e);    
@@ -58,259 +190,943 @@ fn main() {
 
 }
 
-fn handle_connection(mut stream: TcpStream, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) {
-    
-    write_to_stream("Welcome to the Tic Tac Toe server\nType a username",&mut stream);
-    let (mut username, _) = read_from_stream(&mut stream);
-    username = username.as_str().trim().to_string();
-    write_to_stream(format!("Welcome {}!", username).as_str(), &mut stream);
+fn handle_connection(mut stream: TcpStream, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, accounts: Arc<Mutex<AccountStore>>, archive: Arc<Mutex<Archive>>, config: Config) {
+    if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+        println!("Error setting the write timeout: {}", e);
+        return;
+    }
+    let mut reader = FrameReader::new(stream.try_clone().unwrap());
 
-    let (tx, rx) = mpsc::channel::<String>();
+    write_to_stream("Welcome to the Tic Tac Toe server\nType: register <username> <password> or login <username> <password>",&mut stream);
+    let (mode, username) = match handshake(&mut reader, &mut stream, accounts, players.clone()) {
+        Some(login) => login,
+        None => return
+    };
+    let conn = Connection::new(stream, mode);
+    let session_id = NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst);
+    let token = random_token();
+
+    //only one session per user, so messages always reach exactly one connection
+    let mut old_conn = None;
+    let (tx, mailbox, resumed, resumed_game, greeting) = {
+        let mut p = players.lock().unwrap();
+        let existing = p.iter().position(|x| x.username == username);
+        match existing {
+            Some(i) if p[i].connection.is_none() => { //the owner is back within the grace period
+                let player = &mut p[i];
+                player.session_id = session_id;
+                player.token = token.clone();
+                player.connection = Some(conn.clone());
+                player.disconnected_at = None;
+                let greeting = Response::Resumed { username: username.clone(), token };
+                (player.transmission_channel.clone(), player.mailbox.take().unwrap(), true, player.game, greeting)
+            }
+            _ => {
+                let mut challenges = vec![];
+                if let Some(i) = existing {
+                    match config.session_policy {
+                        SessionPolicy::Reject => {
+                            drop(p);
+                            conn.send(ServerMessage::error(ErrorCode::AlreadyLoggedIn, "this account is already logged in from another connection"));
+                            conn.close();
+                            println!("{} was turned away, already logged in", username);
+                            return;
+                        }
+                        SessionPolicy::Takeover => {
+                            let old = p.remove(i);
+                            old_conn = old.connection;
+                            challenges = old.challenges;
+                            println!("{} took over an existing session", username);
+                        }
+                    }
+                }
+                let (tx, rx) = mpsc::channel::<ServerMessage>();
+                let greeting = Response::LoggedIn { username: username.clone(), token: token.clone() };
+                p.push(Player {
+                    username: username.to_string(),
+                    session_id,
+                    token,
+                    game: None,
+                    challenges,
+                    transmission_channel: tx.clone(),
+                    connection: Some(conn.clone()),
+                    disconnected_at: None,
+                    mailbox: None,
+                    replay: None,
+                    seek: None});
+                (tx, Mailbox::new(rx), false, None, greeting)
+            }
+        }
+    };
+    //writing only once the lock is released, either socket may be slow to take it
+    if let Some(old_conn) = old_conn {
+        old_conn.send(ServerMessage::Event(Event::SessionTakenOver));
+        old_conn.close();
+    }
+    conn.send(ServerMessage::Response(greeting));
 
-    let mut info = Player {
-        username: username.to_string(),
-        game: None,
-        challenges: vec![],
-        transmission_channel: tx.clone()};
+    if let Some(i) = resumed_game {
+        conn.send(ServerMessage::Event(Event::GameRecovered));
+        let mut g = games.lock().unwrap();
+        g[i].player_returned(&username);
+        g[i].send_update();
+    }
 
-    { //recovering games from lost connnection
+    let mut recovered = None;
+    if !resumed { //recovering games from lost connnection, the username has been authenticated so only the owner gets here
         let mut g = games.lock().unwrap();
         let len = g.len();
         for (game, i) in g.iter_mut().zip(0..len) {
             if game.win == State::None && (game.player1 == username || game.player2 == username) {
-                info.game = Some(i);
+                recovered = Some(i);
                 if game.player1 == username {
                     game.player1channel = tx.clone();
                 }
                 else if game.player2 == username {
                     game.player2channel = tx.clone();
                 }
-                write_to_stream("game successfully recovered", &mut stream);
+                conn.send(ServerMessage::Event(Event::GameRecovered));
+                game.player_returned(&username);
                 game.send_update();
             }
         }
 
     }
 
-    {
+    if recovered.is_some() {
         let mut p = players.lock().unwrap();
-        p.push(info);
+        for player in p.iter_mut() {
+            if player.session_id == session_id {
+                player.game = recovered;
+            }
+        }
     }
     
-    let mut stream_clone = stream.try_clone().unwrap();
-    thread::spawn(move || {
-        loop {
-            let msg = rx.recv();
-            match msg {
-                Ok(msg) => {write_to_stream(&msg, &mut stream_clone)}
-                _ => {break}} //the player has disconnected
-            }
-    });
+    let writer = conn.clone();
+    let forwarder = thread::spawn(move || forward_messages(writer, mailbox));
     
     println!("{} has logged in", username);    
     loop {
-        let (message, bytes_read) = read_from_stream(&mut stream);
-        if bytes_read == 0 {
-            {
-                let mut p = players.lock().unwrap();
-                p.retain(|x| x.username != username);
+        let message = match read_from_stream(&mut reader) {
+            Some(message) => message,
+            None => { //keeping the session around so the client can resume it
+                conn.close();
+                let mailbox = forwarder.join().unwrap();
+                let mut left_game = None;
+                {
+                    let mut p = players.lock().unwrap();
+                    for player in p.iter_mut() {
+                        if player.session_id == session_id {
+                            player.connection = None;
+                            player.disconnected_at = Some(Instant::now());
+                            player.mailbox = Some(mailbox);
+                            left_game = player.game;
+                            break;
+                        }
+                    }
+                    announce_offline(&username, &p);
+                }
+                if let Some(i) = left_game { //the opponent gets a countdown until the game is forfeited
+                    let mut g = games.lock().unwrap();
+                    g[i].player_left(&username, config.forfeit_after);
+                }
+                println!("{} has disconnected...", username);
+                break
             }
-            println!("{} has disconnected...", username);
-            break
-        }
+        };
 
         let players_clone = players.clone();
         let in_game = is_in_game(&username, players_clone);
 
-        match in_game {
-            Some(game) => {
-                if message.starts_with("resign") {
-                    let players_clone = players.clone();
-                    let games_clone = games.clone();
-                    resign(&username, game, players_clone, games_clone);
-                }
-                else if TIC_TAC_TOE_MOVES.contains(&message.as_str()) {
-                    let players_clone = players.clone();
-                    let games_clone = games.clone();
-                    play_move(&username, &message, game, players_clone, games_clone);
+        let request = match conn.mode {
+            Mode::Json => match parse_json_request(&message) {
+                Some(request) => request,
+                None => {
+                    conn.send(ServerMessage::error(ErrorCode::Usage, "malformed request"));
+                    continue
                 }
             },
-            None => {
-                if message.starts_with("online") {
-                    let players_clone = players.clone();
-                    who_is_online(&mut stream, players_clone);
+            Mode::Text => match parse_text_command(&message, in_game.is_some()) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(usage) => {
+                    conn.send(usage.to_message());
+                    continue
                 }
-                else if message.starts_with("dm ") {
-                    let players_clone = players.clone();
-                    direct_message(&username, &message, &mut stream, players_clone);
+            },
+        };
+
+        if chat_text(&request).is_some_and(|text| text.chars().count() > MAX_CHAT_LENGTH) {
+            conn.send(ServerMessage::error(ErrorCode::Usage, &format!("messages can be at most {} characters", MAX_CHAT_LENGTH)));
+            continue
+        }
+
+        match (in_game, request) {
+            (Some(game), Request::Resign) => {
+                let players_clone = players.clone();
+                let games_clone = games.clone();
+                resign(&username, game, EndReason::Resignation, players_clone, games_clone, archive.clone());
+            }
+            (Some(game), Request::Move { square }) if !is_move(&square, game, games.clone()) => {
+                let games_clone = games.clone();
+                game_chat(&username, &square, game, games_clone);
+            }
+            (Some(game), Request::Move { square }) => {
+                let players_clone = players.clone();
+                let games_clone = games.clone();
+                play_move(&username, &square, game, players_clone, games_clone, archive.clone());
+            }
+            (Some(game), Request::GameChat { text }) => {
+                let games_clone = games.clone();
+                game_chat(&username, &text, game, games_clone);
+            }
+            (None, Request::Online) => {
+                let players_clone = players.clone();
+                who_is_online(&conn, players_clone);
+            }
+            (None, Request::Games) => {
+                let games_clone = games.clone();
+                list_games(&conn, games_clone);
+            }
+            (None, Request::Watch { player }) => {
+                let players_clone = players.clone();
+                let games_clone = games.clone();
+                watch(&username, &player, &conn, players_clone, games_clone);
+            }
+            (None, Request::History { player }) => {
+                history(&username, player.as_deref(), &conn, archive.clone());
+            }
+            (None, Request::Show { id }) => {
+                show(id, &conn, archive.clone());
+            }
+            (None, Request::Stats { player }) => {
+                stats(&username, player.as_deref(), &conn, archive.clone());
+            }
+            (None, Request::Leaderboard { limit }) => {
+                leaderboard(limit.unwrap_or(LEADERBOARD_LENGTH), &conn, archive.clone());
+            }
+            (None, Request::RatingHistory { player }) => {
+                rating_history(&player, &conn, archive.clone());
+            }
+            (None, Request::Export { id }) => {
+                export(id, &conn, archive.clone());
+            }
+            (None, Request::Replay { id, interval_ms }) => {
+                let players_clone = players.clone();
+                replay(&username, id, interval_ms.map(Duration::from_millis), &conn, players_clone, archive.clone());
+            }
+            (None, Request::ReplayNext) => {
+                let players_clone = players.clone();
+                step_replay(&username, Replay::next, &conn, players_clone);
+            }
+            (None, Request::ReplayPrev) => {
+                let players_clone = players.clone();
+                step_replay(&username, Replay::prev, &conn, players_clone);
+            }
+            (None, Request::Unwatch) => {
+                let games_clone = games.clone();
+                unwatch(&username, &conn, games_clone);
+            }
+            (None, Request::Dm { to, text }) => {
+                let players_clone = players.clone();
+                direct_message(&username, &to, &text, &conn, players_clone);
+            }
+            (None, Request::Seek { variant, time_control, rated }) => {
+                let Some(variant) = variant_named(variant, &conn) else { continue };
+                let settings = GameSettings { variant, time_control, position: None, rated };
+                let players_clone = players.clone();
+                seek(&username, settings, &conn, players_clone, archive.clone());
+            }
+            (None, Request::CancelSeek) => {
+                let players_clone = players.clone();
+                cancel_seek(&username, &conn, players_clone);
+            }
+            (None, Request::Challenge { opponent, variant, time_control, difficulty, position, color, rated }) => {
+                let Some(variant) = variant_named(variant, &conn) else { continue };
+                let position = match position.map(|position| variant.normalize_position(&position)) {
+                    Some(Ok(position)) => Some(position),
+                    Some(Err(e)) => {
+                        conn.send(ServerMessage::error(ErrorCode::InvalidPosition, &e));
+                        continue
+                    }
+                    None => None,
+                };
+                if rated == Some(true) && position.is_some() {
+                    conn.send(ServerMessage::error(ErrorCode::Usage, "games from a position can't be rated"));
+                    continue
                 }
-                else if message.starts_with("challenge ") {
-                    let players_clone = players.clone();
-                    challenge(&username, &message, &mut stream, players_clone);
+                let settings = GameSettings { variant, time_control, rated: rated.unwrap_or(position.is_none()), position };
+                let challenge = Challenge { from: username.clone(), settings, color: color.unwrap_or_default(), sent_at: Instant::now() };
+                let players_clone = players.clone();
+                if opponent == BOT_NAME && rated == Some(true) {
+                    conn.send(ServerMessage::error(ErrorCode::Usage, "games against the bot can't be rated"));
                 }
-                else if message.starts_with("accept ") {
-                    let players_clone = players.clone();
+                else if opponent == BOT_NAME {
                     let games_clone = games.clone();
-                    let (opponent, game_index) = accept(&username, &message, &mut stream, players_clone, games_clone);
-                    match opponent {
-                        Some(opponent) => {
-                            {
-                                let mut p = players.lock().unwrap();
-                                for player in p.iter_mut() {
-                                    if player.username == username || player.username == opponent {
-                                        player.game = game_index;
-                                        player.challenges.retain(|x|x != &opponent );
-                                    }
-                                }
-                            }
-                        }
-                        None => {}
-                    }
+                    play_bot(challenge, difficulty.unwrap_or_default(), &conn, players_clone, games_clone, archive.clone());
+                }
+                else if difficulty.is_some() {
+                    conn.send(ServerMessage::error(ErrorCode::Usage, "only the bot has a difficulty"));
                 }
                 else {
-                    let players_clone = players.clone();
-                    global_message(&username, &message, players_clone);
+                    send_challenge(challenge, &opponent, &conn, players_clone);
                 }
             }
+            (None, Request::Accept { opponent }) => {
+                let players_clone = players.clone();
+                let games_clone = games.clone();
+                accept(&username, &opponent, &|message| conn.send(message), players_clone, games_clone);
+            }
+            (None, Request::Decline { opponent }) => {
+                let players_clone = players.clone();
+                decline(&username, &opponent, &conn, players_clone);
+            }
+            (None, Request::Withdraw { opponent }) => {
+                let players_clone = players.clone();
+                withdraw(&username, &opponent, &conn, players_clone);
+            }
+            (None, Request::Challenges) => {
+                let players_clone = players.clone();
+                list_challenges(&username, config.challenge_timeout, &conn, players_clone);
+            }
+            (None, Request::Chat { text }) => {
+                let players_clone = players.clone();
+                global_message(&username, &text, players_clone);
+            }
+            (Some(_), _) => conn.send(ServerMessage::error(ErrorCode::Unavailable, "not available during a game")),
+            (None, _) => conn.send(ServerMessage::error(ErrorCode::Unavailable, "not available outside a game")),
         }
     }
 }
 
-fn read_from_stream(stream: &mut TcpStream) -> (String, usize) {
-    let mut buffer = [0; 1024];
-    
-    let bytes_read = stream.read(&mut buffer).unwrap_or_else(|e| {
-        eprintln!("Error while reading stream: {}", e);
-        return 0;
-    });
-    let message_len = match buffer.iter().position(|&x| x == b'\0') {
-        Some(index) => index,
-        None => bytes_read
+/// Reads the first frame of a connection. A JSON `Hello` switches the connection to the
+/// structured protocol, anything else is read as a text mode command. Either way the
+/// client then has to register, log in or resume a session before it gets any further.
+fn handshake(reader: &mut FrameReader<TcpStream>, stream: &mut TcpStream, accounts: Arc<Mutex<AccountStore>>, players: Arc<Mutex<Vec<Player>>>) -> Option<(Mode, String)> {
+    let mut message = read_from_stream(reader)?;
+    let mode = match parse_json_request(&message) {
+        Some(Request::Hello { version }) => {
+            if version != PROTOCOL_VERSION {
+                let error = ServerMessage::error(ErrorCode::UnsupportedVersion,
+                    &format!("server speaks protocol version {}", PROTOCOL_VERSION));
+                write_to_stream(&Mode::Json.encode(&error), stream);
+                return None;
+            }
+            let hello = ServerMessage::Response(Response::Hello { version: PROTOCOL_VERSION });
+            write_to_stream(&Mode::Json.encode(&hello), stream);
+            message = read_from_stream(reader)?;
+            Mode::Json
+        }
+        _ => Mode::Text,
     };
-    
-    (String::from_utf8_lossy(&buffer[..message_len]).to_string(), bytes_read)
+
+    loop {
+        let request = match mode {
+            Mode::Json => parse_json_request(&message)
+                .ok_or_else(|| ServerMessage::error(ErrorCode::Usage, "expected a register or login request")),
+            Mode::Text => parse_login_command(&message).map_err(|usage| usage.to_message()),
+        };
+        let result = match request {
+            Ok(Request::Register { username, password }) => {
+                let mut a = accounts.lock().unwrap();
+                a.register(&username, &password)
+                .map(|_| username)
+                .map_err(|e| ServerMessage::error(ErrorCode::RegistrationFailed, &e.to_string()))
+            }
+            Ok(Request::Login { username, password }) => {
+                let a = accounts.lock().unwrap();
+                a.verify(&username, &password)
+                .map(|_| username)
+                .map_err(|e| ServerMessage::error(ErrorCode::AuthFailed, &e.to_string()))
+            }
+            Ok(Request::Resume { token }) => {
+                let p = players.lock().unwrap();
+                p.iter()
+                .find(|player| player.token == token)
+                .map(|player| player.username.clone())
+                .ok_or_else(|| ServerMessage::error(ErrorCode::AuthFailed, "unknown or expired session token"))
+            }
+            Ok(_) => Err(ServerMessage::error(ErrorCode::Usage, "expected a register or login request")),
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(username) => return Some((mode, username)),
+            Err(error) => write_to_stream(&mode.encode(&error), stream),
+        }
+        message = read_from_stream(reader)?;
+    }
 }
 
-fn write_to_stream(message: &str, stream: &mut TcpStream) {
-    stream.write(message.as_bytes()).unwrap();
-    stream.flush().unwrap();
+/// The text of a request that's passed on to other players, a move included since one
+/// word game chat arrives as a move.
+fn chat_text(request: &Request) -> Option<&str> {
+    match request {
+        Request::Chat { text } | Request::GameChat { text } | Request::Dm { text, .. } => Some(text),
+        Request::Move { square } => Some(square),
+        _ => None,
+    }
 }
 
 fn global_message(username: &str, message: &str, players: Arc<Mutex<Vec<Player>>>) {
         {
             let p = players.lock().unwrap();
             for player in p.iter() {
-                match player.game {
-                    Some(_) => {},
-                    None => {
-                        if player.username != username {
-                            player.transmission_channel.send(format!("{}: {}", username, message)).unwrap();
-                        }
-                    }           
+                if player.game.is_none() && player.username != username {
+                    player.transmission_channel.send(ServerMessage::Event(Event::Chat {
+                        from: username.to_string(),
+                        text: message.to_string(),
+                    })).unwrap();
                 }
             }
         }
 }
 
-fn direct_message(username: &str, message: &str, stream: &mut TcpStream, players: Arc<Mutex<Vec<Player>>>) {
-    let split_message = message.split_whitespace().collect::<Vec<&str>>();
-    if split_message.len() >= 3 {
-        let (player_username, dm) = (split_message[1], split_message[2..].join(" ")); 
-        {
-            let p = players.lock().unwrap();
-            for player in p.iter() {
-                if player.username == player_username {
-                    player.transmission_channel.send(format!("dm from {}: {}", username, dm)).unwrap();
-                    write_to_stream(format!("dm sent to {}", player_username).as_str(), stream);
-                }
+fn game_chat(username: &str, message: &str, game_index: usize, games: Arc<Mutex<Vec<Game>>>) {
+    let g = games.lock().unwrap();
+    g[game_index].chat(username, message);
+}
+
+/// Whether the game can read `input` as a move, legal or not. Players type moves and chat
+/// on the same line, in text mode and through the client alike, so a single word that
+/// isn't a move is chat.
+fn is_move(input: &str, game_index: usize, games: Arc<Mutex<Vec<Game>>>) -> bool {
+    let g = games.lock().unwrap();
+    g[game_index].rules.parse_move(input).is_some()
+}
+
+fn direct_message(username: &str, player_username: &str, dm: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let sent = {
+        let p = players.lock().unwrap();
+        match p.iter().find(|player| player.username == player_username) {
+            Some(player) => {
+                let _ = player.transmission_channel.send(ServerMessage::Event(Event::DirectMessage {
+                    from: username.to_string(),
+                    text: dm.to_string(),
+                }));
+                true
             }
+            None => false,
         }
+    };
+    if sent {
+        conn.send(ServerMessage::Response(Response::DmSent { to: player_username.to_string() }));
     }
     else {
-        write_to_stream("use format: dm <user> <message>", stream);
+        conn.send(ServerMessage::error(ErrorCode::NotOnline, &format!("{} is not online", player_username)));
     }
 }
 
-fn challenge(username: &str, message: &str, stream: &mut TcpStream, players: Arc<Mutex<Vec<Player>>>) {
-    let split_message = message.split_whitespace().collect::<Vec<&str>>();
-    if split_message.len() == 2 {
-        let player_username = split_message[1]; 
-        {
+/// The game a challenge or seek names, the default game if it names none. Tells the
+/// player if there is no such game.
+fn variant_named(name: Option<String>, conn: &Connection) -> Option<Variant> {
+    match name {
+        Some(name) => {
+            let variant = Variant::from_name(&name);
+            if variant.is_none() {
+                conn.send(ServerMessage::error(ErrorCode::UnknownVariant, &format!("unknown game {}, choose {}", name, Variant::NAMES)));
+            }
+            variant
+        }
+        None => Some(Variant::default()),
+    }
+}
+
+fn send_challenge(challenge: Challenge, player_username: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let reply = {
+        let mut p = players.lock().unwrap();
+        match p.iter_mut().find(|player| player.username == player_username && player.disconnected_at.is_none()) {
+            Some(player) if player.game.is_some() => {
+                ServerMessage::error(ErrorCode::Busy, &format!("{} is in a game, try again later... ", player_username))
+            }
+            Some(player) => {
+                let _ = player.transmission_channel.send(ServerMessage::Event(Event::ChallengeReceived {
+                    from: challenge.from.clone(),
+                    variant: challenge.settings.variant.name(),
+                    time_control: challenge.settings.time_control,
+                    position: challenge.settings.position.clone(),
+                    rated: challenge.settings.rated,
+                    color: challenge.color,
+                }));
+                //a new challenge replaces the one sent before
+                player.challenges.retain(|x| x.from != challenge.from);
+                player.challenges.push(challenge);
+                ServerMessage::Response(Response::ChallengeSent { to: player_username.to_string() })
+            }
+            None => ServerMessage::error(ErrorCode::NotOnline, &format!("{} is not online, try again later... ", player_username)),
+        }
+    };
+    conn.send(reply);
+}
+
+/// Starts the game `opponent_username` challenged `username` to. Replies go through `reply`,
+/// which is the connection for people and a log line for engines.
+fn accept(username: &str, opponent_username: &str, reply: &dyn Fn(ServerMessage), players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) -> Option<usize> {
+    let mut challenge = None;
+    let mut opponent_online = false;
+    let mut opponent_busy = false;
+
+    {  //checking if challanges contains the opponent and if opponent is online
+        let p = players.lock().unwrap();
+        for player in p.iter() {
+            if player.username == username {
+                challenge = player.challenges.iter().rfind(|x| x.from == opponent_username).cloned();
+            }
+            else if player.username == opponent_username {
+                opponent_online = player.disconnected_at.is_none();
+                opponent_busy = player.game.is_some();
+            }
+        }
+    }
+    let Some(challenge) = challenge else {
+        reply(ServerMessage::error(ErrorCode::NoChallenge, &format!("no challenge from {}", opponent_username)));
+        return None
+    };
+    if !opponent_online {
+        reply(ServerMessage::error(ErrorCode::NotOnline, &format!("{} is not online, try again later... ", opponent_username)));
+        return None
+    }
+    if opponent_busy {
+        reply(ServerMessage::error(ErrorCode::Busy, &format!("{} is in a game, try again later... ", opponent_username)));
+        return None
+    }
+    let announcements = vec![
+        (opponent_username.to_string(), ServerMessage::Event(Event::ChallengeAccepted { by: username.to_string() })),
+        (username.to_string(), ServerMessage::Response(Response::ChallengeAccepted { opponent: opponent_username.to_string() })),
+    ];
+    let (x, o) = if challenger_plays_x(challenge.color) { (opponent_username, username) } else { (username, opponent_username) };
+    let started = start_game(x, o, challenge.settings, announcements, players.clone(), games);
+    match started {
+        Some(_) => {
             let mut p = players.lock().unwrap();
             for player in p.iter_mut() {
-                if player.username == player_username {
-                    match player.game {
-                        Some(_) => {
-                            write_to_stream(format!("{} is in a game, try again later... \n", player_username).as_str(), stream);
-                            return;
-                        },
-                        None => {}
-                    }
-                    player.challenges.push(username.to_string());
-                    player.transmission_channel.send(format!("challenge from {}\nType: accept {} to play", username, username)).unwrap();
-                    write_to_stream(format!("challenge sent to {}", player_username).as_str(), stream);
-                    return;
+                if player.username == username || player.username == opponent_username {
+                    player.challenges.retain(|x|x.from != opponent_username );
                 }
             }
-            write_to_stream(format!("{} is not online, try again later... \n", player_username).as_str(), stream);
         }
+        None => reply(ServerMessage::error(ErrorCode::Busy, &format!("{} is in a game, try again later... ", opponent_username))),
+    }
+    started
+}
+
+/// Settles the challenger's side, tossing a coin if they left it to chance.
+fn challenger_plays_x(color: ColorChoice) -> bool {
+    match color {
+        ColorChoice::X => true,
+        ColorChoice::O => false,
+        ColorChoice::Random => rand::random(),
+    }
+}
+
+/// Turns down the challenge `opponent_username` sent to `username`.
+fn decline(username: &str, opponent_username: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let declined = {
+        let mut p = players.lock().unwrap();
+        let Some(player) = p.iter_mut().find(|player| player.username == username) else { return };
+        let open = player.challenges.len();
+        player.challenges.retain(|x| x.from != opponent_username);
+        let declined = player.challenges.len() < open;
+        if let Some(opponent) = p.iter().find(|player| player.username == opponent_username).filter(|_| declined) {
+            let _ = opponent.transmission_channel.send(ServerMessage::Event(Event::ChallengeClosed {
+                from: opponent_username.to_string(),
+                to: username.to_string(),
+                reason: ChallengeEnd::Declined,
+            }));
+        }
+        declined
+    };
+    if declined {
+        conn.send(ServerMessage::Response(Response::ChallengeDeclined { from: opponent_username.to_string() }));
     }
     else {
-        write_to_stream("use format: challenge <user>", stream);
+        conn.send(ServerMessage::error(ErrorCode::NoChallenge, &format!("no challenge from {}", opponent_username)));
     }
 }
 
-fn accept(username: &str, message: &str, stream: &mut TcpStream, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) -> (Option<String>, Option<usize>) {
-    
-    let split_message = message.split_whitespace().collect::<Vec<&str>>();
-    let (mut p1_transmission_channel,mut p2_transmission_channel): 
-    (Option<mpsc::Sender<String>>,Option<mpsc::Sender<String>>) = (None, None);
+/// Takes back the challenge `username` sent to `opponent_username`.
+fn withdraw(username: &str, opponent_username: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let withdrawn = {
+        let mut p = players.lock().unwrap();
+        let withdrawn = match p.iter_mut().find(|player| player.username == opponent_username) {
+            Some(opponent) => {
+                let open = opponent.challenges.len();
+                opponent.challenges.retain(|x| x.from != username);
+                if opponent.challenges.len() < open {
+                    let _ = opponent.transmission_channel.send(ServerMessage::Event(Event::ChallengeClosed {
+                        from: username.to_string(),
+                        to: opponent_username.to_string(),
+                        reason: ChallengeEnd::Withdrawn,
+                    }));
+                }
+                opponent.challenges.len() < open
+            }
+            None => false,
+        };
+        withdrawn
+    };
+    if withdrawn {
+        conn.send(ServerMessage::Response(Response::ChallengeWithdrawn { to: opponent_username.to_string() }));
+    }
+    else {
+        conn.send(ServerMessage::error(ErrorCode::NoChallenge, &format!("no challenge to {}", opponent_username)));
+    }
+}
 
-    if split_message.len() == 2 {
-        let opponent_username = split_message[1]; 
-        {  //checking if challanges contains the opponent and if opponent is in a game
-            let p = players.lock().unwrap();
-            for player in p.iter() {
+/// Lists the open challenges `username` received and sent.
+fn list_challenges(username: &str, timeout: Duration, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let mut incoming = vec![];
+    let mut outgoing = vec![];
+    {
+        let p = players.lock().unwrap();
+        for player in p.iter() {
+            for challenge in player.challenges.iter() {
                 if player.username == username {
-                    p2_transmission_channel = Some(player.transmission_channel.clone());
-                    if !player.challenges.contains(&opponent_username.to_string()) {
-                        return (None, None);
-                    }
-                        
+                    incoming.push(pending_challenge(&challenge.from, challenge, timeout));
                 }
-                else if player.username == opponent_username {
-                    p1_transmission_channel = Some(player.transmission_channel.clone());
+                else if challenge.from == username {
+                    outgoing.push(pending_challenge(&player.username, challenge, timeout));
                 }
             }
         }
-        match (&p1_transmission_channel, &p2_transmission_channel) {
-            (Some(_), Some(_)) => {}
-            _ => {return (None, None)} //both players are not online
+    }
+    conn.send(ServerMessage::Response(Response::Challenges { incoming, outgoing }));
+}
+
+/// `challenge` as it's listed, `player` being the other side of it.
+fn pending_challenge(player: &str, challenge: &Challenge, timeout: Duration) -> PendingChallenge {
+    PendingChallenge {
+        player: player.to_string(),
+        variant: challenge.settings.variant.name(),
+        time_control: challenge.settings.time_control,
+        position: challenge.settings.position.clone(),
+        rated: challenge.settings.rated,
+        color: challenge.color,
+        expires_in: timeout.saturating_sub(challenge.sent_at.elapsed()).as_secs(),
+    }
+}
+
+/// Tells both sides of a challenge that it's off.
+fn challenge_closed(p: &[Player], from: &str, to: &str, reason: ChallengeEnd) {
+    for player in p.iter().filter(|player| player.username == from || player.username == to) {
+        let _ = player.transmission_channel.send(ServerMessage::Event(Event::ChallengeClosed {
+            from: from.to_string(),
+            to: to.to_string(),
+            reason,
+        }));
+    }
+}
+
+/// Tells everyone with a pending challenge to or from `username` that they lost their
+/// connection. The challenges stay until they expire or the session does.
+fn announce_offline(username: &str, p: &[Player]) {
+    let received = p.iter()
+        .find(|player| player.username == username)
+        .map(|player| player.challenges.iter().map(|x| x.from.clone()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    for player in p.iter() {
+        if received.contains(&player.username) || player.challenges.iter().any(|x| x.from == username) {
+            let _ = player.transmission_channel.send(ServerMessage::Event(Event::ChallengePartyOffline {
+                player: username.to_string(),
+            }));
+        }
+    }
+}
+
+/// Drops every challenge sent to or by a player whose session is over.
+fn drop_challenges(username: &str, p: &mut [Player]) {
+    let mut closed = vec![];
+    for player in p.iter_mut() {
+        if player.username == username {
+            closed.extend(player.challenges.drain(..).map(|x| (x.from, username.to_string(), ChallengeEnd::ChallengedLeft)));
         }
-        {  //accepting the challange
-            let mut p = players.lock().unwrap();
-            for player in p.iter_mut() {
-                if player.username == opponent_username {
-                    player.transmission_channel.send(format!("{} has accepted your challange", username)).unwrap();
-                    write_to_stream(format!("accepted challege with {}\n", opponent_username).as_str(), stream);       
-                    {
-                        let mut g = games.lock().unwrap();
-                        let game_index = g.len();
-                        let new_game = Game::new(opponent_username.to_string(), username.to_string(),
-                        p1_transmission_channel.unwrap(), p2_transmission_channel.unwrap());
-                        new_game.send_update();
-                        g.push(new_game);
-                        return (Some(opponent_username.to_string()), Some(game_index));
-                    }
-                    
+        else {
+            player.challenges.retain(|x| {
+                if x.from == username {
+                    closed.push((username.to_string(), player.username.clone(), ChallengeEnd::ChallengerLeft));
                 }
+                x.from != username
+            });
+        }
+    }
+    for (from, to, reason) in closed {
+        challenge_closed(p, &from, &to, reason);
+    }
+}
+
+/// Sets up a game between two players, `x` playing X, and sends them the board. Both stop
+/// watching other games and seeking. `None` if either of them is gone.
+/// Starts a game unless `x` or `o` has left or is already playing. Both locks are held from
+/// the check until the players are marked as playing, so a challenge being accepted and a
+/// seek being matched can't put the same player in two games. The `announcements` go to the
+/// players named with them only once the game is starting, ahead of the first board.
+fn start_game(x: &str, o: &str, settings: GameSettings, announcements: Vec<(String, ServerMessage)>, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) -> Option<usize> {
+    let mut g = games.lock().unwrap();
+    let mut p = players.lock().unwrap();
+    let channel = |username: &str| p.iter()
+        .find(|player| player.username == username && player.game.is_none())
+        .map(|player| player.transmission_channel.clone());
+    let (channel1, channel2) = (channel(x)?, channel(o)?);
+    for (username, message) in announcements {
+        if let Some(player) = p.iter().find(|player| player.username == username) {
+            let _ = player.transmission_channel.send(message);
+        }
+    }
+    for game in g.iter_mut() { //players don't watch other games while they play
+        game.remove_spectator(x);
+        game.remove_spectator(o);
+    }
+    let mut game = Game::new(settings.variant, x.to_string(), o.to_string(), channel1, channel2, settings.time_control, settings.position);
+    game.rated = settings.rated;
+    game.send_update();
+    g.push(game);
+    let game_index = g.len() - 1;
+    for player in p.iter_mut() {
+        if player.username == x || player.username == o {
+            player.game = Some(game_index);
+            player.seek = None;
+        }
+    }
+    Some(game_index)
+}
+
+/// Puts `username` in the matchmaking queue, in place of whatever they were seeking before.
+fn seek(username: &str, settings: GameSettings, conn: &Connection, players: Arc<Mutex<Vec<Player>>>, archive: Arc<Mutex<Archive>>) {
+    let rating = {
+        let a = archive.lock().unwrap();
+        a.ratings.stats(username).rating
+    };
+    let seeking = Response::Seeking {
+        variant: settings.variant.name(),
+        time_control: settings.time_control,
+        rated: settings.rated,
+    };
+    {
+        let mut p = players.lock().unwrap();
+        let Some(player) = p.iter_mut().find(|player| player.username == username) else { return };
+        player.seek = Some(Seek { settings, rating, since: Instant::now() });
+    }
+    conn.send(ServerMessage::Response(seeking));
+}
+
+fn cancel_seek(username: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let seek = {
+        let mut p = players.lock().unwrap();
+        p.iter_mut().find(|player| player.username == username).and_then(|player| player.seek.take())
+    };
+    match seek {
+        Some(_) => conn.send(ServerMessage::Response(Response::SeekCancelled)),
+        None => conn.send(ServerMessage::error(ErrorCode::NotSeeking, "you are not seeking a game")),
+    }
+}
+
+/// Pairs up seekers who want the same kind of game. Whoever has waited longest goes first
+/// and gets the opponent with the closest rating within their range, which widens the
+/// longer they wait. Colors are drawn at random.
+fn match_seekers(players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) {
+    let pairs = {
+        let p = players.lock().unwrap();
+        let mut seekers = p.iter()
+            .filter(|player| player.disconnected_at.is_none() && player.game.is_none())
+            .filter_map(|player| player.seek.clone().map(|seek| (player.username.clone(), seek)))
+            .collect::<Vec<_>>();
+        seekers.sort_by_key(|(_, seek)| seek.since);
+        let mut matched = vec![false; seekers.len()];
+        let mut pairs = vec![];
+        for i in 0..seekers.len() {
+            if matched[i] {
+                continue;
+            }
+            let seek = &seekers[i].1;
+            let range = SEEK_RATING_RANGE + SEEK_RANGE_GROWTH * seek.since.elapsed().as_secs() as i32;
+            let closest = (0..seekers.len())
+                .filter(|&j| j != i && !matched[j] && seekers[j].1.settings == seek.settings)
+                .map(|j| (j, (seekers[j].1.rating - seek.rating).abs()))
+                .filter(|&(_, difference)| difference <= range)
+                .min_by_key(|&(_, difference)| difference);
+            if let Some((j, _)) = closest {
+                matched[i] = true;
+                matched[j] = true;
+                pairs.push((seekers[i].clone(), seekers[j].clone()));
             }
-            return (None, None)
         }
+        pairs
+    };
+    for ((a, seek_a), (b, seek_b)) in pairs {
+        let announcements = vec![
+            (a.clone(), ServerMessage::Event(Event::Matched { opponent: b.clone(), opponent_rating: seek_b.rating })),
+            (b.clone(), ServerMessage::Event(Event::Matched { opponent: a.clone(), opponent_rating: seek_a.rating })),
+        ];
+        let (x, o) = if rand::random() { (a, b) } else { (b, a) };
+        start_game(&x, &o, seek_a.settings, announcements, players.clone(), games.clone());
     }
-    else {
-        write_to_stream("use format: accept <user>", stream);
-        return (None, None);
+}
+
+/// Starts a game against the built-in bot straight away, the bot doesn't need to accept.
+/// The challenger plays the side they asked for, and the game isn't rated.
+fn play_bot(challenge: Challenge, difficulty: Difficulty, conn: &Connection, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>) {
+    let username = challenge.from.as_str();
+    let channel = {
+        let p = players.lock().unwrap();
+        match p.iter().find(|player| player.username == username) {
+            Some(player) => player.transmission_channel.clone(),
+            None => return,
+        }
+    };
+    let bot_name = format!("{} ({})", BOT_NAME, difficulty);
+    let (bot_tx, bot_rx) = mpsc::channel::<ServerMessage>();
+    conn.send(ServerMessage::Response(Response::ChallengeAccepted { opponent: bot_name.clone() }));
+    let game_index = {
+        let mut g = games.lock().unwrap();
+        for game in g.iter_mut() {
+            game.remove_spectator(username);
+        }
+        let settings = challenge.settings;
+        let mut game = if challenger_plays_x(challenge.color) {
+            Game::new(settings.variant, username.to_string(), bot_name.clone(), channel, bot_tx, settings.time_control, settings.position)
+        }
+        else {
+            Game::new(settings.variant, bot_name.clone(), username.to_string(), bot_tx, channel, settings.time_control, settings.position)
+        };
+        game.rated = false;
+        g.push(game);
+        g.len() - 1
+    };
+    {
+        let mut p = players.lock().unwrap();
+        for player in p.iter_mut() {
+            if player.username == username {
+                player.game = Some(game_index);
+                player.seek = None;
+            }
+        }
+    }
+    {
+        let players = players.clone();
+        let games = games.clone();
+        let archive = archive.clone();
+        thread::spawn(move || run_bot(bot_name, difficulty, bot_rx, game_index, players, games, archive));
+    }
+    let g = games.lock().unwrap();
+    g[game_index].send_update();
+}
+
+/// Plays the bot's side of a game, moving whenever a board update says it's the bot's turn.
+fn run_bot(name: String, difficulty: Difficulty, messages: mpsc::Receiver<ServerMessage>, game_index: usize, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>) {
+    for message in messages.iter() {
+        match message {
+            ServerMessage::Event(Event::BoardUpdate { your_turn: true, .. }) => {
+                let (rules, side) = {
+                    let g = games.lock().unwrap();
+                    let game = &g[game_index];
+                    (game.rules.clone(), if game.player1 == name { State::X } else { State::O })
+                };
+                if let Some(mv) = choose_move(rules.as_ref(), side, difficulty) {
+                    play_move(&name, &rules.format_move(mv), game_index, players.clone(), games.clone(), archive.clone());
+                }
+            }
+            ServerMessage::Event(Event::GameOver { .. }) => break,
+            _ => {}
+        }
+    }
+}
+
+/// Logs an external engine in as a player that anyone can challenge.
+fn start_engine(engine_config: &EngineConfig, timeout: Duration, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, accounts: Arc<Mutex<AccountStore>>, archive: Arc<Mutex<Archive>>) {
+    let name = engine_config.name.clone();
+    accounts.lock().unwrap().reserve(&name)
+    .unwrap_or_else(|e| panic!("Error adding engine {}: {}", name, e));
+    let engine = Engine::spawn(&engine_config.command)
+    .unwrap_or_else(|e| panic!("Error starting engine {}: {}", name, e));
+    let (tx, rx) = mpsc::channel::<ServerMessage>();
+    {
+        let mut p = players.lock().unwrap();
+        p.push(Player {
+            username: name.clone(),
+            session_id: NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst),
+            token: random_token(),
+            game: None,
+            challenges: vec![],
+            transmission_channel: tx,
+            connection: None,
+            disconnected_at: None,
+            mailbox: None,
+            replay: None,
+            seek: None});
+    }
+    println!("engine {} is ready", name);
+    let engine_config = engine_config.clone();
+    thread::spawn(move || run_engine(engine_config, engine, rx, timeout, players, games, archive));
+}
+
+/// Plays for an external engine, accepting every challenge it gets. An engine that crashes,
+/// takes too long or answers with an illegal move forfeits the game, and is restarted for
+/// the next one.
+fn run_engine(engine_config: EngineConfig, engine: Engine, messages: mpsc::Receiver<ServerMessage>, timeout: Duration, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>) {
+    let EngineConfig { name, command } = engine_config;
+    let mut engine = Some(engine);
+    for message in messages.iter() {
+        match message {
+            ServerMessage::Event(Event::ChallengeReceived { from, .. }) => {
+                let reply = |message: ServerMessage| if let ServerMessage::Response(Response::Error { message, .. }) = message {
+                    println!("{} could not accept {}'s challenge: {}", name, from, message);
+                };
+                let game_index = match accept(&name, &from, &reply, players.clone(), games.clone()) {
+                    Some(game_index) => game_index,
+                    None => continue,
+                };
+                if engine.is_none() {
+                    engine = Engine::spawn(&command)
+                    .map_err(|e| println!("Error restarting engine {}: {}", name, e))
+                    .ok();
+                }
+                let (variant, position) = {
+                    let g = games.lock().unwrap();
+                    (g[game_index].variant.name(), g[game_index].start_position.clone())
+                };
+                let started = match engine.as_mut() {
+                    Some(engine) => engine.new_game(&variant, position.as_deref()),
+                    None => Err(EngineError::Crashed),
+                };
+                if let Err(e) = started {
+                    println!("{} forfeits: {}", name, e);
+                    engine = None;
+                    resign(&name, game_index, EndReason::EngineFailure, players.clone(), games.clone(), archive.clone());
+                }
+            }
+            ServerMessage::Event(Event::BoardUpdate { your_turn: true, .. }) => {
+                let game_index = match is_in_game(&name, players.clone()) {
+                    Some(game_index) => game_index,
+                    None => continue,
+                };
+                let (rules, moves) = {
+                    let g = games.lock().unwrap();
+                    let game = &g[game_index];
+                    if game.turn != name || game.win != State::None { //an update that was queued before the position moved on
+                        continue;
+                    }
+                    (game.rules.clone(), game.moves.iter().map(|played| game.rules.format_move(played.mv)).collect::<Vec<_>>())
+                };
+                let answer = match engine.as_mut() {
+                    Some(engine) => engine.best_move(&moves, timeout),
+                    None => Err(EngineError::Crashed),
+                };
+                match answer {
+                    Ok(mv) if rules.parse_move(&mv).is_some_and(|mv| rules.is_legal(mv)) => {
+                        play_move(&name, &mv, game_index, players.clone(), games.clone(), archive.clone());
+                    }
+                    Ok(mv) => {
+                        println!("{} forfeits: {} is not a legal move", name, mv);
+                        resign(&name, game_index, EndReason::EngineFailure, players.clone(), games.clone(), archive.clone());
+                    }
+                    Err(e) => {
+                        println!("{} forfeits: {}", name, e);
+                        engine = None;
+                        resign(&name, game_index, EndReason::EngineFailure, players.clone(), games.clone(), archive.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
     }
 }
 
@@ -329,61 +1145,443 @@ fn is_in_game(username: &str, players: Arc<Mutex<Vec<Player>>>) -> Option<usize>
     None
 }
 
-fn play_move(username: &str, m: &str, game_index: usize, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) {
-    let square = m.parse().unwrap();
+fn play_move(username: &str, m: &str, game_index: usize, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>) {
     {
         let mut g = games.lock().unwrap();
         let game = &mut g[game_index];
-        let ok = game.play_move(username, square);
-        if !ok {return}
-        let is_over = game.check_for_result();
+        let flagged = game.check_clock();
+        let is_over = flagged || {
+            let ok = game.play_move(username, m);
+            if !ok {return}
+            game.check_for_result()
+        };
         if is_over {
             {
                 let mut p = players.lock().unwrap();
                 for player in p.iter_mut() {
-                    match player.game {
-                        Some(i) => {
-                            if i == game_index {
-                                player.game = None;
-                            }
-                        }
-                        None => {}
+                    if player.game == Some(game_index) {
+                        player.game = None;
                     }
                 }
             }
+            archive_game(game, archive);
         }
     }
 }
 
-fn resign(username: &str, game_index: usize, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) {
+/// Ends the game with a loss for `username`, who resigned or forfeited.
+fn resign(username: &str, game_index: usize, reason: EndReason, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>) {
         {
             let mut g = games.lock().unwrap();
             let game = &mut g[game_index];
-            game.player1channel.send(format!("{} resigned the game\n", username)).unwrap();
-            game.player2channel.send(format!("{} resigned the game\n", username)).unwrap();
-            game.win = State::Draw; //preventing the game from being recovered
+            if game.win != State::None { //already over, e.g. an engine failing just as its clock ran out
+                return;
+            }
+            game.award_win(username, reason);
+            archive_game(game, archive);
         }
         {
             let mut p = players.lock().unwrap();
             for player in p.iter_mut() {
-                match player.game {
-                    Some(i) => {
-                        if i == game_index {
-                            player.game = None;
-                        }
-                    }
-                    None => {}
+                if player.game == Some(game_index) {
+                    player.game = None;
                 }
             }
         }
 }
 
-fn who_is_online(stream: &mut TcpStream, players: Arc<Mutex<Vec<Player>>>,) {
-    write_to_stream("Online players:\n", stream);
+/// Writes a finished game to the archive.
+fn archive_game(game: &Game, archive: Arc<Mutex<Archive>>) {
+    let mut a = archive.lock().unwrap();
+    let id = a.next_id();
+    if let Some(record) = game.record(id) {
+        if let Err(e) = a.add(record) {
+            println!("Error archiving game: {}", e);
+        }
+    }
+}
+
+fn history(username: &str, player_username: Option<&str>, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let player = player_username.unwrap_or(username).to_string();
+    let games = {
+        let a = archive.lock().unwrap();
+        a.history(&player, HISTORY_LENGTH)
+    };
+    conn.send(ServerMessage::Response(Response::History { player, games }));
+}
+
+fn show(id: u64, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let record = {
+        let a = archive.lock().unwrap();
+        a.get(id).cloned()
+    };
+    match record {
+        Some(game) => conn.send(ServerMessage::Response(Response::Game { game })),
+        None => conn.send(ServerMessage::error(ErrorCode::UnknownGame, &format!("there is no game #{}", id))),
+    }
+}
+
+fn stats(username: &str, player_username: Option<&str>, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let player = player_username.unwrap_or(username).to_string();
+    let stats = {
+        let a = archive.lock().unwrap();
+        a.ratings.stats(&player)
+    };
+    conn.send(ServerMessage::Response(Response::Stats { player, stats }));
+}
+
+fn leaderboard(limit: usize, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let players = {
+        let a = archive.lock().unwrap();
+        a.ratings.leaderboard(limit.min(MAX_LEADERBOARD_LENGTH))
+    };
+    conn.send(ServerMessage::Response(Response::Leaderboard { players }));
+}
+
+fn rating_history(player_username: &str, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let points = {
+        let a = archive.lock().unwrap();
+        a.ratings.history(player_username)
+    };
+    conn.send(ServerMessage::Response(Response::RatingHistory { player: player_username.to_string(), points }));
+}
+
+fn export(id: u64, conn: &Connection, archive: Arc<Mutex<Archive>>) {
+    let notation = {
+        let a = archive.lock().unwrap();
+        a.get(id).map(write_notation)
+    };
+    match notation {
+        Some(notation) => conn.send(ServerMessage::Response(Response::Export { id, notation })),
+        None => conn.send(ServerMessage::error(ErrorCode::UnknownGame, &format!("there is no game #{}", id))),
+    }
+}
+
+/// Starts a replay of a finished game for `username`, in place of any replay they had going.
+/// With an `interval` the positions are sent at that pace on a thread of their own.
+fn replay(username: &str, id: u64, interval: Option<Duration>, conn: &Connection, players: Arc<Mutex<Vec<Player>>>, archive: Arc<Mutex<Archive>>) {
+    let record = {
+        let a = archive.lock().unwrap();
+        a.get(id).cloned()
+    };
+    let Some(record) = record else {
+        conn.send(ServerMessage::error(ErrorCode::UnknownGame, &format!("there is no game #{}", id)));
+        return
+    };
+    let replay = match Replay::new(record) {
+        Ok(replay) => replay,
+        Err(e) => {
+            conn.send(ServerMessage::error(ErrorCode::Unavailable, &format!("game #{} can't be replayed: {}", id, e)));
+            return
+        }
+    };
+    let serial = replay.serial;
+    let messages = replay.messages();
     {
+        let mut p = players.lock().unwrap();
+        let Some(player) = p.iter_mut().find(|player| player.username == username) else { return };
+        player.replay = Some(replay);
+    }
+    for message in messages {
+        conn.send(message);
+    }
+    if let Some(interval) = interval {
+        let username = username.to_string();
+        thread::spawn(move || autoplay_replay(&username, serial, interval, players));
+    }
+}
+
+/// Steps through the replay with the given serial until it reaches the end, or the player
+/// starts another replay or leaves.
+fn autoplay_replay(username: &str, serial: u64, interval: Duration, players: Arc<Mutex<Vec<Player>>>) {
+    loop {
+        thread::sleep(interval);
+        let mut p = players.lock().unwrap();
+        let Some(player) = p.iter_mut().find(|player| player.username == username) else { return };
+        let Some(replay) = player.replay.as_mut().filter(|replay| replay.serial == serial) else { return };
+        if !replay.next() {
+            return
+        }
+        for message in replay.messages() {
+            let _ = player.transmission_channel.send(message);
+        }
+    }
+}
+
+/// Moves the player's replay with `step` and sends the position it lands on.
+fn step_replay(username: &str, step: fn(&mut Replay) -> bool, conn: &Connection, players: Arc<Mutex<Vec<Player>>>) {
+    let messages = {
+        let mut p = players.lock().unwrap();
+        let Some(player) = p.iter_mut().find(|player| player.username == username) else { return };
+        match player.replay.as_mut() {
+            Some(replay) => {
+                if step(replay) {
+                    replay.messages()
+                }
+                else {
+                    vec![ServerMessage::error(ErrorCode::NoReplay, "no more moves that way")]
+                }
+            }
+            None => vec![ServerMessage::error(ErrorCode::NoReplay, "start a replay first with: replay <game id>")],
+        }
+    };
+    for message in messages {
+        conn.send(message);
+    }
+}
+
+fn who_is_online(conn: &Connection, players: Arc<Mutex<Vec<Player>>>,) {
+    let online = {
         let p = players.lock().unwrap();
-        for player in p.iter() {
-            write_to_stream(format!("{}  ", &player.username).as_str(), stream);
+        p.iter()
+        .filter(|player| player.disconnected_at.is_none())
+        .map(|player| player.username.clone())
+        .collect()
+    };
+    conn.send(ServerMessage::Response(Response::Online { players: online }));
+}
+
+fn list_games(conn: &Connection, games: Arc<Mutex<Vec<Game>>>) {
+    let summaries = {
+        let g = games.lock().unwrap();
+        g.iter()
+        .filter(|game| game.win == State::None)
+        .map(|game| LiveGame {
+            variant: game.variant.name(),
+            x: game.player1.clone(),
+            o: game.player2.clone(),
+            spectators: game.spectators.len(),
+        })
+        .collect()
+    };
+    conn.send(ServerMessage::Response(Response::Games { games: summaries }));
+}
+
+/// Makes `username` a spectator of the game `player_username` is playing, instead of
+/// whatever they were watching before.
+fn watch(username: &str, player_username: &str, conn: &Connection, players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>) {
+    let channel = {
+        let p = players.lock().unwrap();
+        match p.iter().find(|player| player.username == username) {
+            Some(player) => player.transmission_channel.clone(),
+            None => return,
+        }
+    };
+    let mut g = games.lock().unwrap();
+    let found = g.iter().position(|game| game.win == State::None && (game.player1 == player_username || game.player2 == player_username));
+    match found {
+        Some(i) => {
+            for game in g.iter_mut() {
+                game.remove_spectator(username);
+            }
+            let game = &mut g[i];
+            //through the channel, so it goes out ahead of the board the game sends
+            let _ = channel.send(ServerMessage::Response(Response::Watching { x: game.player1.clone(), o: game.player2.clone() }));
+            game.add_spectator(username, channel);
+        }
+        None => {
+            drop(g);
+            conn.send(ServerMessage::error(ErrorCode::NotPlaying, &format!("{} is not playing a game", player_username)));
         }
     }
-}
\ No newline at end of file
+}
+
+fn unwatch(username: &str, conn: &Connection, games: Arc<Mutex<Vec<Game>>>) {
+    let mut watched = false;
+    {
+        let mut g = games.lock().unwrap();
+        for game in g.iter_mut() {
+            watched |= game.remove_spectator(username);
+        }
+    }
+    if watched {
+        conn.send(ServerMessage::Response(Response::Unwatched));
+    }
+    else {
+        conn.send(ServerMessage::error(ErrorCode::NotPlaying, "you are not watching a game"));
+    }
+}
+
+/// Drops sessions whose client has been away for longer than the grace period. Players in
+/// a game are kept until it's over, the forfeit countdown decides what happens to them.
+fn expire_sessions(players: Arc<Mutex<Vec<Player>>>, grace: Duration) {
+    let mut p = players.lock().unwrap();
+    let expired = p.iter()
+        .filter(|player| player.disconnected_at.is_some_and(|at| at.elapsed() > grace) && player.game.is_none())
+        .map(|player| player.username.clone())
+        .collect::<Vec<String>>();
+    for username in expired.iter() {
+        println!("{}'s session expired", username);
+        drop_challenges(username, &mut p);
+    }
+    p.retain(|player| !expired.contains(&player.username));
+}
+
+/// Drops challenges that have been open for longer than `timeout`.
+fn expire_challenges(players: Arc<Mutex<Vec<Player>>>, timeout: Duration) {
+    let mut p = players.lock().unwrap();
+    let mut expired = vec![];
+    for player in p.iter_mut() {
+        player.challenges.retain(|x| {
+            let open = x.sent_at.elapsed() <= timeout;
+            if !open {
+                expired.push((x.from.clone(), player.username.clone()));
+            }
+            open
+        });
+    }
+    for (from, to) in expired {
+        challenge_closed(&p, &from, &to, ChallengeEnd::Expired);
+    }
+}
+
+/// Ends the games of players who haven't come back within the forfeit window or whose
+/// clock has run out.
+fn adjudicate_games(players: Arc<Mutex<Vec<Player>>>, games: Arc<Mutex<Vec<Game>>>, archive: Arc<Mutex<Archive>>, forfeit_after: Duration) {
+    let mut finished = vec![];
+    {
+        let mut g = games.lock().unwrap();
+        for (i, game) in g.iter_mut().enumerate() {
+            if game.check_absences(forfeit_after) || game.check_clock() {
+                archive_game(game, archive.clone());
+                finished.push(i);
+            }
+        }
+    }
+    if !finished.is_empty() {
+        let mut p = players.lock().unwrap();
+        for player in p.iter_mut() {
+            if player.game.is_some_and(|i| finished.contains(&i)) {
+                player.game = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A connected player, with the receiving end of their channel standing in for the client.
+    fn player(username: &str) -> (Player, mpsc::Receiver<ServerMessage>) {
+        let (tx, rx) = mpsc::channel();
+        let player = Player {
+            username: username.to_string(),
+            session_id: NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst),
+            token: random_token(),
+            game: None,
+            challenges: vec![],
+            transmission_channel: tx,
+            connection: None,
+            disconnected_at: None,
+            mailbox: None,
+            replay: None,
+            seek: None,
+        };
+        (player, rx)
+    }
+
+    /// The server end of a loopback connection to send replies on, and the client end,
+    /// which has to stay open for the writes to go through.
+    fn connection() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (Connection::new(listener.accept().unwrap().0, Mode::Json), client)
+    }
+
+    fn challenge(from: &str, sent_at: Instant) -> Challenge {
+        Challenge {
+            from: from.to_string(),
+            settings: GameSettings { variant: Variant::Mnk { columns: 3, rows: 3, k: 3 }, time_control: None, position: None, rated: false },
+            color: ColorChoice::X,
+            sent_at,
+        }
+    }
+
+    /// The `ChallengeClosed` events a player was sent since the last look.
+    fn closed(rx: &mpsc::Receiver<ServerMessage>) -> Vec<(String, String, ChallengeEnd)> {
+        rx.try_iter().filter_map(|message| match message {
+            ServerMessage::Event(Event::ChallengeClosed { from, to, reason }) => Some((from, to, reason)),
+            _ => None,
+        }).collect()
+    }
+
+    /// Who a player was told went offline since the last look.
+    fn offline(rx: &mpsc::Receiver<ServerMessage>) -> Vec<String> {
+        rx.try_iter().filter_map(|message| match message {
+            ServerMessage::Event(Event::ChallengePartyOffline { player }) => Some(player),
+            _ => None,
+        }).collect()
+    }
+
+    fn pending(username: &str, players: &Arc<Mutex<Vec<Player>>>) -> Vec<String> {
+        let p = players.lock().unwrap();
+        let player = p.iter().find(|player| player.username == username).unwrap();
+        player.challenges.iter().map(|x| x.from.clone()).collect()
+    }
+
+    fn close(from: &str, to: &str, reason: ChallengeEnd) -> (String, String, ChallengeEnd) {
+        (from.to_string(), to.to_string(), reason)
+    }
+
+    #[test]
+    fn challenges_can_be_replaced_declined_withdrawn_and_expire() {
+        let (alice, alice_rx) = player("alice");
+        let (bob, bob_rx) = player("bob");
+        let (carol, carol_rx) = player("carol");
+        let players = Arc::new(Mutex::new(vec![alice, bob, carol]));
+        let (conn, _client) = connection();
+
+        send_challenge(challenge("alice", Instant::now()), "bob", &conn, players.clone());
+        send_challenge(challenge("alice", Instant::now()), "bob", &conn, players.clone());
+        assert_eq!(pending("bob", &players), ["alice"], "the second challenge replaces the first");
+
+        decline("bob", "alice", &conn, players.clone());
+        assert!(pending("bob", &players).is_empty());
+        assert_eq!(closed(&alice_rx), [close("alice", "bob", ChallengeEnd::Declined)]);
+        assert!(closed(&bob_rx).is_empty(), "bob gets a reply instead");
+
+        send_challenge(challenge("alice", Instant::now()), "bob", &conn, players.clone());
+        withdraw("alice", "bob", &conn, players.clone());
+        assert!(pending("bob", &players).is_empty());
+        assert!(closed(&alice_rx).is_empty(), "alice gets a reply instead");
+        assert_eq!(closed(&bob_rx), [close("alice", "bob", ChallengeEnd::Withdrawn)]);
+
+        let a_minute_ago = Instant::now() - Duration::from_secs(60);
+        send_challenge(challenge("alice", a_minute_ago), "bob", &conn, players.clone());
+        send_challenge(challenge("carol", Instant::now()), "bob", &conn, players.clone());
+        expire_challenges(players.clone(), Duration::from_secs(30));
+        assert_eq!(pending("bob", &players), ["carol"]);
+        assert_eq!(closed(&alice_rx), [close("alice", "bob", ChallengeEnd::Expired)]);
+        assert_eq!(closed(&bob_rx), [close("alice", "bob", ChallengeEnd::Expired)]);
+        assert!(closed(&carol_rx).is_empty());
+    }
+
+    #[test]
+    fn challenges_are_held_while_a_player_is_away_and_dropped_with_the_session() {
+        let (alice, alice_rx) = player("alice");
+        let (bob, _bob_rx) = player("bob");
+        let (carol, carol_rx) = player("carol");
+        let players = Arc::new(Mutex::new(vec![alice, bob, carol]));
+        let (conn, _client) = connection();
+
+        send_challenge(challenge("alice", Instant::now()), "bob", &conn, players.clone());
+        send_challenge(challenge("bob", Instant::now()), "carol", &conn, players.clone());
+        {
+            let mut p = players.lock().unwrap();
+            let bob = p.iter_mut().find(|player| player.username == "bob").unwrap();
+            bob.disconnected_at = Some(Instant::now() - Duration::from_secs(60));
+            announce_offline("bob", &p);
+        }
+        assert_eq!(offline(&alice_rx), ["bob"]);
+        assert_eq!(offline(&carol_rx), ["bob"]);
+        assert_eq!(pending("bob", &players), ["alice"]);
+        assert_eq!(pending("carol", &players), ["bob"]);
+
+        expire_sessions(players.clone(), Duration::from_secs(30));
+        assert!(players.lock().unwrap().iter().all(|player| player.username != "bob"));
+        assert!(pending("carol", &players).is_empty());
+        assert_eq!(closed(&alice_rx), [close("alice", "bob", ChallengeEnd::ChallengedLeft)]);
+        assert_eq!(closed(&carol_rx), [close("bob", "carol", ChallengeEnd::ChallengerLeft)]);
+    }
+}
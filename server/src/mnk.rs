@@ -0,0 +1,315 @@
+use crate::game::*;
+
+/// Boards up to this many squares take squares numbered from the top left, like the
+/// original 3x3 game. Bigger ones are played with coordinates.
+const NUMBERED_SQUARES: usize = 9;
+
+/// An m,n,k game: `k` in a row on a `columns` by `rows` board wins. Tic Tac Toe is 3,3,3 and
+/// Gomoku 15,15,5.
+///
+/// Squares can be given as coordinates, a column letter from `a` on the left and a row
+/// number from 1 at the bottom, e.g. `h8`.
+#[derive(Debug, Clone)]
+pub struct MnkBoard {
+    columns: usize,
+    rows: usize,
+    k: usize,
+    /// Row by row from the top.
+    pub board: Vec<State>,
+    /// Set as soon as a move completes a line, so only lines through that move need checking.
+    winner: State,
+    filled: usize,
+}
+
+impl MnkBoard {
+    pub fn new(columns: usize, rows: usize, k: usize) -> MnkBoard {
+        MnkBoard {
+            columns,
+            rows,
+            k,
+            board: vec![State::None; columns * rows],
+            winner: State::None,
+            filled: 0,
+        }
+    }
+
+    /// Reads a position string: the rows from the top separated by `/`, each cell an `x` or
+    /// an `o` and runs of empty cells a number, then `:` and the side to move. The empty
+    /// Tic Tac Toe board with X to move is `3/3/3:x`. Positions that are already won or full
+    /// aren't accepted, there would be nothing left to play.
+    pub fn from_position(columns: usize, rows: usize, k: usize, position: &str) -> Result<(MnkBoard, State), String> {
+        let (cells, side) = position.split_once(':').ok_or("a position ends with :x or :o for the side to move")?;
+        let to_move = match side.to_ascii_lowercase().as_str() {
+            "x" => State::X,
+            "o" => State::O,
+            _ => return Err(format!("{} is not a side, use x or o", side)),
+        };
+        let lines = cells.split('/').collect::<Vec<&str>>();
+        if lines.len() != rows {
+            return Err(format!("the board has {} rows, not {}", rows, lines.len()));
+        }
+        let mut board = MnkBoard::new(columns, rows, k);
+        let mut squares = vec![];
+        for line in lines {
+            let mut row = vec![];
+            let mut empty = String::new();
+            for c in line.chars().chain(std::iter::once('/')) {
+                if c.is_ascii_digit() {
+                    empty.push(c);
+                    continue;
+                }
+                if !empty.is_empty() {
+                    let count = empty.parse::<usize>().map_err(|_| format!("{} is too many squares", empty))?;
+                    row.extend(std::iter::repeat_n(State::None, count.min(columns + 1)));
+                    empty.clear();
+                }
+                match c.to_ascii_lowercase() {
+                    'x' => row.push(State::X),
+                    'o' => row.push(State::O),
+                    '/' => {}
+                    other => return Err(format!("{} is not a cell, use x, o or a number of empty squares", other)),
+                }
+            }
+            if row.len() != columns {
+                return Err(format!("every row has {} squares", columns));
+            }
+            squares.extend(row);
+        }
+        for (square, side) in squares.into_iter().enumerate() {
+            if side != State::None {
+                board.apply(square, side);
+            }
+        }
+        if board.outcome() != State::None {
+            return Err("the game is already over in that position".to_string());
+        }
+        Ok((board, to_move))
+    }
+
+    /// The inverse of `from_position`, with the shortest way of writing every row.
+    pub fn to_position(&self, to_move: State) -> String {
+        let rows = self.board.chunks(self.columns).map(|row| {
+            let mut line = String::new();
+            let mut empty = 0;
+            for &square in row {
+                if square == State::None {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    line.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                line.push(if square == State::X { 'x' } else { 'o' });
+            }
+            if empty > 0 {
+                line.push_str(&empty.to_string());
+            }
+            line
+        }).collect::<Vec<String>>();
+        format!("{}:{}", rows.join("/"), if to_move == State::O { 'o' } else { 'x' })
+    }
+
+    fn numbered(&self) -> bool {
+        self.board.len() <= NUMBERED_SQUARES
+    }
+
+    fn parse_coordinate(&self, input: &str) -> Option<usize> {
+        let mut chars = input.chars();
+        let letter = chars.next()?.to_ascii_lowercase();
+        if !letter.is_ascii_lowercase() {
+            return None;
+        }
+        let column = (letter as u8 - b'a') as usize;
+        let row = chars.as_str().parse::<usize>().ok()?;
+        if column >= self.columns || row == 0 || row > self.rows {
+            return None;
+        }
+        Some((self.rows - row) * self.columns + column)
+    }
+
+    /// How many of `side`'s stones follow `square` in one direction, not counting `square`.
+    fn run(&self, square: usize, d_row: isize, d_column: isize, side: State) -> usize {
+        let mut row = (square / self.columns) as isize;
+        let mut column = (square % self.columns) as isize;
+        let mut count = 0;
+        loop {
+            row += d_row;
+            column += d_column;
+            if row < 0 || row >= self.rows as isize || column < 0 || column >= self.columns as isize {
+                return count;
+            }
+            if self.board[row as usize * self.columns + column as usize] != side {
+                return count;
+            }
+            count += 1;
+        }
+    }
+
+    fn completes_line(&self, square: usize, side: State) -> bool {
+        [(0, 1), (1, 0), (1, 1), (1, -1)].iter().any(|&(d_row, d_column)| {
+            1 + self.run(square, d_row, d_column, side) + self.run(square, -d_row, -d_column, side) >= self.k
+        })
+    }
+}
+
+impl GameRules for MnkBoard {
+    fn initial() -> MnkBoard {
+        MnkBoard::new(3, 3, 3)
+    }
+
+    fn parse_move(&self, input: &str) -> Option<usize> {
+        match input.parse::<usize>() {
+            Ok(square) if self.numbered() && (1..=self.board.len()).contains(&square) => Some(square - 1),
+            _ => self.parse_coordinate(input),
+        }
+    }
+
+    fn format_move(&self, mv: usize) -> String {
+        if self.numbered() {
+            return (mv + 1).to_string();
+        }
+        let column = (b'a' + (mv % self.columns) as u8) as char;
+        format!("{}{}", column, self.rows - mv / self.columns)
+    }
+
+    fn move_count(&self) -> usize {
+        self.board.len()
+    }
+
+    fn is_legal(&self, mv: usize) -> bool {
+        self.outcome() == State::None && self.board[mv] == State::None
+    }
+
+    fn apply(&mut self, mv: usize, side: State) {
+        self.board[mv] = side;
+        self.filled += 1;
+        if self.completes_line(mv, side) {
+            self.winner = side;
+        }
+    }
+
+    fn outcome(&self) -> State {
+        if self.winner != State::None {
+            return self.winner;
+        }
+        if self.filled == self.board.len() {
+            return State::Draw;
+        }
+        State::None
+    }
+
+    fn render(&self) -> Vec<Vec<String>> {
+        self.board
+            .chunks(self.columns)
+            .map(|row| row.iter().map(|square| format!("{:?}", square)).collect())
+            .collect()
+    }
+
+    fn move_hint(&self) -> String {
+        let last_column = (b'a' + self.columns as u8 - 1) as char;
+        if self.numbered() {
+            format!("type 1-{} to play a move", self.board.len())
+        }
+        else {
+            format!("type a square from a1 (bottom left) to {}{} (top right), {} in a row wins", last_column, self.rows, self.k)
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn GameRules> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `squares` for `side`, returning the outcome after each move.
+    fn play(board: &mut MnkBoard, side: State, squares: &[&str]) -> Vec<State> {
+        squares.iter().map(|square| {
+            let mv = board.parse_move(square).unwrap();
+            assert!(board.is_legal(mv), "{} should be legal", square);
+            board.apply(mv, side);
+            board.outcome()
+        }).collect()
+    }
+
+    #[test]
+    fn finds_lines_on_a_4x4_board() {
+        let mut board = MnkBoard::new(4, 4, 4);
+        play(&mut board, State::O, &["a4", "b3", "c2"]);
+        assert_eq!(play(&mut board, State::X, &["a1", "b1", "c1", "d1"]), [State::None, State::None, State::None, State::X]);
+        assert!(!board.is_legal(board.parse_move("d4").unwrap()));
+    }
+
+    #[test]
+    fn finds_diagonals_along_the_edges_of_a_gomoku_board() {
+        let mut board = MnkBoard::new(15, 15, 5);
+        assert_eq!(play(&mut board, State::X, &["a1", "b2", "c3", "e5", "d4"]).last(), Some(&State::X));
+
+        let mut board = MnkBoard::new(15, 15, 5);
+        assert_eq!(play(&mut board, State::O, &["o1", "n2", "m3", "l4"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::O, &["k5"]), [State::O]);
+
+        let mut board = MnkBoard::new(15, 15, 5);
+        assert_eq!(play(&mut board, State::X, &["k15", "l14", "m13", "n12", "o11"]).last(), Some(&State::X));
+    }
+
+    #[test]
+    fn lines_longer_than_k_win_too() {
+        let mut board = MnkBoard::new(5, 5, 4);
+        assert_eq!(play(&mut board, State::X, &["a1", "b1", "d1", "e1"]).last(), Some(&State::None));
+        assert_eq!(play(&mut board, State::X, &["c1"]), [State::X]);
+
+        let mut board = MnkBoard::new(5, 5, 4);
+        assert_eq!(play(&mut board, State::X, &["a1", "a2", "a3"]).last(), Some(&State::None));
+    }
+
+    #[test]
+    fn parses_coordinates_within_the_board() {
+        let board = MnkBoard::new(15, 15, 5);
+        assert_eq!(board.parse_move("a1"), Some(14 * 15));
+        assert_eq!(board.parse_move("A1"), Some(14 * 15));
+        assert_eq!(board.parse_move("o15"), Some(14));
+        assert_eq!(board.parse_move("h8"), Some(7 * 15 + 7));
+        for square in ["p1", "a16", "a0", "1", "", "a", "aa1"] {
+            assert_eq!(board.parse_move(square), None, "{} is off the board", square);
+        }
+        assert_eq!(board.format_move(14), "o15");
+
+        let tic_tac_toe = MnkBoard::initial();
+        assert_eq!(tic_tac_toe.parse_move("1"), Some(0));
+        assert_eq!(tic_tac_toe.parse_move("c3"), Some(2));
+        assert_eq!(tic_tac_toe.parse_move("10"), None);
+    }
+
+    #[test]
+    fn writes_positions_back_the_way_they_were_read() {
+        let positions = [
+            (3, 3, "3/3/3:x"),
+            (3, 3, "x1o/1x1/o2:o"),
+            (5, 3, "2x2/5/o4:x"),
+            (15, 15, "15/15/15/15/15/15/15/7x7/15/15/15/15/15/15/15:o"),
+        ];
+        for (columns, rows, position) in positions {
+            let (board, to_move) = MnkBoard::from_position(columns, rows, 3, position).unwrap();
+            assert_eq!(board.to_position(to_move), position);
+        }
+    }
+
+    #[test]
+    fn reads_positions_leniently() {
+        let (board, to_move) = MnkBoard::from_position(3, 3, 3, "X2/3/2O:X").unwrap();
+        assert_eq!(to_move, State::X);
+        assert_eq!(board.to_position(to_move), "x2/3/2o:x");
+        assert_eq!(board.filled, 2);
+    }
+
+    #[test]
+    fn rejects_bad_positions() {
+        for position in ["3/3/3", "3/3:x", "4/3/3:x", "2/3/3:x", "3/3/3:z", "x1q/3/3:x", "xxx/oo1/3:o", "xox/xox/oxo:x", "99999999999999999999/3/3:x"] {
+            assert!(MnkBoard::from_position(3, 3, 3, position).is_err(), "{} should be rejected", position);
+        }
+    }
+}
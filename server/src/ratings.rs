@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use protocol::*;
+
+use crate::storage::*;
+
+/// Everyone starts here.
+const INITIAL_RATING: f64 = 1500.0;
+/// The most a rating can move in one game.
+const K_FACTOR: f64 = 32.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    /// Kept unrounded so small changes add up.
+    rating: f64,
+    games: u32,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    /// Games lost by resigning, also counted in `losses`.
+    resignations: u32,
+    /// The rating after every rated game, oldest first.
+    #[serde(default)]
+    history: Vec<RatingPoint>,
+}
+
+impl Default for Entry {
+    fn default() -> Entry {
+        Entry { rating: INITIAL_RATING, games: 0, wins: 0, losses: 0, draws: 0, resignations: 0, history: vec![] }
+    }
+}
+
+/// Elo ratings and results per player, kept in a JSON file. Every finished game counts
+/// towards the results, only rated games move the ratings.
+pub struct Ratings {
+    path: PathBuf,
+    players: HashMap<String, Entry>,
+}
+
+impl Ratings {
+    pub fn load(path: &Path) -> io::Result<Ratings> {
+        Ok(Ratings { path: path.to_path_buf(), players: load_json(path)? })
+    }
+
+    pub fn add(&mut self, game: &GameRecord) -> io::Result<()> {
+        let x = self.players.get(&game.x).cloned().unwrap_or_default();
+        let o = self.players.get(&game.o).cloned().unwrap_or_default();
+        let score = match game.outcome {
+            Outcome::X => 1.0,
+            Outcome::O => 0.0,
+            Outcome::Draw => 0.5,
+        };
+        // the expected score of X, from the difference between the ratings
+        let expected = 1.0 / (1.0 + 10f64.powf((o.rating - x.rating) / 400.0));
+        let change = if game.rated { K_FACTOR * (score - expected) } else { 0.0 };
+        self.players.insert(game.x.clone(), updated(x, Outcome::X, change, game));
+        self.players.insert(game.o.clone(), updated(o, Outcome::O, -change, game));
+        save_json(&self.path, &self.players)
+    }
+
+    /// The `limit` highest rated players who have played a rated game, best first.
+    pub fn leaderboard(&self, limit: usize) -> Vec<LeaderboardEntry> {
+        let mut rated = self.players
+            .iter()
+            .filter(|(_, entry)| !entry.history.is_empty())
+            .collect::<Vec<_>>();
+        rated.sort_by(|(a_name, a), (b_name, b)| b.rating.total_cmp(&a.rating).then(a_name.cmp(b_name)));
+        rated
+            .into_iter()
+            .take(limit)
+            .map(|(username, entry)| LeaderboardEntry {
+                username: username.clone(),
+                rating: entry.rating.round() as i32,
+                rated_games: entry.history.len() as u32,
+            })
+            .collect()
+    }
+
+    pub fn history(&self, username: &str) -> Vec<RatingPoint> {
+        self.players.get(username).map(|entry| entry.history.clone()).unwrap_or_default()
+    }
+
+    pub fn stats(&self, username: &str) -> PlayerStats {
+        let entry = self.players.get(username).cloned().unwrap_or_default();
+        PlayerStats {
+            rating: entry.rating.round() as i32,
+            games: entry.games,
+            wins: entry.wins,
+            losses: entry.losses,
+            draws: entry.draws,
+            resignations: entry.resignations,
+        }
+    }
+}
+
+/// `entry` after playing `side` in `game`.
+fn updated(mut entry: Entry, side: Outcome, change: f64, game: &GameRecord) -> Entry {
+    entry.rating += change;
+    entry.games += 1;
+    if game.rated {
+        entry.history.push(RatingPoint {
+            game: game.id,
+            at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            rating: entry.rating.round() as i32,
+        });
+    }
+    match game.outcome {
+        Outcome::Draw => entry.draws += 1,
+        winner if winner == side => entry.wins += 1,
+        _ => {
+            entry.losses += 1;
+            if game.reason == EndReason::Resignation {
+                entry.resignations += 1;
+            }
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(x: &str, o: &str, outcome: Outcome, reason: EndReason, rated: bool) -> GameRecord {
+        GameRecord {
+            id: 1,
+            variant: "tictactoe".to_string(),
+            x: x.to_string(),
+            o: o.to_string(),
+            time_control: None,
+            rated,
+            start_position: None,
+            started_at: 0,
+            outcome,
+            reason,
+            moves: vec![],
+        }
+    }
+
+    #[test]
+    fn rated_games_move_ratings_and_all_games_count() {
+        let path = std::env::temp_dir().join(format!("ratings-test-{}.json", std::process::id()));
+        let mut ratings = Ratings::load(&path).unwrap();
+        ratings.add(&game("alice", "bob", Outcome::X, EndReason::Line, true)).unwrap();
+        assert_eq!(ratings.stats("alice").rating, 1516);
+        assert_eq!(ratings.stats("bob").rating, 1484);
+
+        ratings.add(&game("bob", "alice", Outcome::O, EndReason::Resignation, false)).unwrap();
+        ratings.add(&game("alice", "bob", Outcome::Draw, EndReason::BoardFull, true)).unwrap();
+        let alice = ratings.stats("alice");
+        assert_eq!((alice.games, alice.wins, alice.losses, alice.draws), (3, 2, 0, 1));
+        let bob = ratings.stats("bob");
+        assert_eq!((bob.losses, bob.resignations), (2, 1));
+        // the favourite drawing loses a little
+        assert!(alice.rating < 1516 && alice.rating + bob.rating == 3000);
+
+        let history = ratings.history("bob").iter().map(|point| point.rating).collect::<Vec<_>>();
+        assert_eq!(history, [1484, bob.rating]);
+        let leaders = ratings.leaderboard(10).into_iter().map(|entry| entry.username).collect::<Vec<_>>();
+        assert_eq!(leaders, ["alice", "bob"]);
+        ratings.add(&game("carol", "dave", Outcome::X, EndReason::Line, false)).unwrap();
+        assert_eq!(ratings.leaderboard(10).len(), 2);
+        assert_eq!(ratings.leaderboard(1).len(), 1);
+
+        let reloaded = Ratings::load(&path).unwrap();
+        assert_eq!(reloaded.stats("alice"), alice);
+        assert_eq!(reloaded.history("alice").len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::{atomic::{AtomicU64, Ordering}, mpsc};
+
+use protocol::*;
+
+use crate::game::*;
+
+static NEXT_REPLAY_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A finished game being stepped through, one position at a time.
+pub struct Replay {
+    /// Tells apart successive replays of the same player, so an automatic playback stops
+    /// once the player starts another one.
+    pub serial: u64,
+    record: GameRecord,
+    game: Game,
+    /// How many moves into the game the replay is.
+    ply: usize,
+}
+
+impl Replay {
+    /// Sets up the game in `record` again. Fails if the record doesn't hold together,
+    /// which only happens if the archive was edited by hand.
+    pub fn new(record: GameRecord) -> Result<Replay, LoadError> {
+        // nobody is playing, so nothing is ever sent to the players
+        let (channel, _) = mpsc::channel();
+        let game = Game::from_record(&record, channel.clone(), channel)?;
+        Ok(Replay { serial: NEXT_REPLAY_ID.fetch_add(1, Ordering::SeqCst), record, game, ply: 0 })
+    }
+
+    /// Returns false if already at the last position.
+    pub fn next(&mut self) -> bool {
+        if self.ply < self.game.moves.len() {
+            self.ply += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Returns false if already at the start.
+    pub fn prev(&mut self) -> bool {
+        if self.ply > 0 {
+            self.ply -= 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// The current position, rendered like the board updates of a live game.
+    pub fn messages(&self) -> Vec<ServerMessage> {
+        let total = self.record.moves.len();
+        let finished = self.ply == total;
+        let (rules, side) = self.game.position_after(self.ply);
+        let step = Event::ReplayStep {
+            id: self.record.id,
+            ply: self.ply,
+            total,
+            last_move: self.ply.checked_sub(1).map(|i| self.record.moves[i].mv.clone()),
+            last_side: self.ply.checked_sub(1).map(|_| if side == State::X { Outcome::O } else { Outcome::X }),
+            outcome: if finished { Some(self.record.outcome) } else { None },
+            reason: if finished { Some(self.record.reason) } else { None },
+        };
+        let to_move = match (finished, side) {
+            (true, _) => None,
+            (false, State::X) => Some(self.record.x.clone()),
+            (false, _) => Some(self.record.o.clone()),
+        };
+        let board = Event::BoardUpdate {
+            variant: self.record.variant.clone(),
+            x: self.record.x.clone(),
+            o: self.record.o.clone(),
+            board: rules.render(),
+            clocks: None,
+            to_move,
+            move_hint: String::new(),
+            your_turn: false,
+            spectating: true,
+        };
+        vec![ServerMessage::Event(step), ServerMessage::Event(board)]
+    }
+}
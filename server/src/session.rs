@@ -0,0 +1,124 @@
+use std::{
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use protocol::*;
+
+/// How often a forwarding thread checks whether its connection has gone away.
+const FORWARD_POLL: Duration = Duration::from_millis(200);
+
+/// A connected client. Writes from the connection thread and from the thread forwarding
+/// `transmission_channel` messages share the stream, so frames never interleave.
+#[derive(Clone)]
+pub struct Connection {
+    stream: Arc<Mutex<TcpStream>>,
+    closed: Arc<AtomicBool>,
+    pub mode: Mode,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, mode: Mode) -> Connection {
+        Connection {
+            stream: Arc::new(Mutex::new(stream)),
+            closed: Arc::new(AtomicBool::new(false)),
+            mode,
+        }
+    }
+
+    /// Gives up on the connection if the write fails or times out, since part of the frame
+    /// may have gone out and the client could no longer tell where the next one starts.
+    /// A message too large for a frame is dropped before anything is written, so the
+    /// connection stays usable.
+    pub fn send(&self, message: ServerMessage) {
+        if self.is_closed() {
+            return;
+        }
+        let mut stream = self.stream.lock().unwrap();
+        match write_frame(&mut *stream, &self.mode.encode(&message)) {
+            Ok(()) => {}
+            Err(e @ FrameError::TooLarge(_)) => eprintln!("Dropped a message: {}", e),
+            Err(e) => {
+                eprintln!("Error while writing stream: {}", e);
+                self.closed.store(true, Ordering::SeqCst);
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    /// Shuts the socket down, which also ends the read loop of the connection's thread.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let stream = self.stream.lock().unwrap();
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// Everything that was sent to a player but not yet delivered. It outlives the connection so
+/// that a resumed session gets the messages it missed while it was away.
+pub struct Mailbox {
+    receiver: mpsc::Receiver<ServerMessage>,
+    backlog: Vec<ServerMessage>,
+}
+
+impl Mailbox {
+    pub fn new(receiver: mpsc::Receiver<ServerMessage>) -> Mailbox {
+        Mailbox { receiver, backlog: vec![] }
+    }
+}
+
+/// Writes the mailbox to the connection until the connection is closed, then hands the mailbox
+/// back with whatever couldn't be delivered.
+pub fn forward_messages(conn: Connection, mut mailbox: Mailbox) -> Mailbox {
+    let mut backlog = std::mem::take(&mut mailbox.backlog).into_iter();
+    for message in backlog.by_ref() {
+        if conn.is_closed() {
+            mailbox.backlog.push(message);
+            break;
+        }
+        conn.send(message);
+    }
+    if conn.is_closed() {
+        mailbox.backlog.extend(backlog);
+        return mailbox;
+    }
+
+    loop {
+        match mailbox.receiver.recv_timeout(FORWARD_POLL) {
+            Ok(message) if conn.is_closed() => {
+                mailbox.backlog.push(message);
+                return mailbox;
+            }
+            Ok(message) => conn.send(message),
+            Err(RecvTimeoutError::Timeout) if conn.is_closed() => return mailbox,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return mailbox,
+        }
+    }
+}
+
+pub fn read_from_stream(reader: &mut FrameReader<TcpStream>) -> Option<String> {
+    match reader.read_frame() {
+        Ok(message) => Some(message),
+        Err(FrameError::Closed) => None,
+        Err(e) => {
+            eprintln!("Error while reading stream: {}", e);
+            None
+        }
+    }
+}
+
+pub fn write_to_stream(message: &str, stream: &mut TcpStream) {
+    if let Err(e) = write_frame(stream, message) {
+        eprintln!("Error while writing stream: {}", e);
+    }
+}
@@ -0,0 +1,33 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Everything the server persists lives under this directory.
+pub const DATA_DIR: &str = "data";
+
+pub fn data_path(file: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(file)
+}
+
+/// Loads a JSON file, starting from the default value if it doesn't exist yet. Whatever the
+/// server keeps across restarts is written with `save_json` and read back with this.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes to a temporary file first so a crash never leaves a half written file behind.
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    fs::rename(tmp, path)
+}
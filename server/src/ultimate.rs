@@ -0,0 +1,200 @@
+use crate::game::*;
+
+/// Lines through a 3x3 grid, for the sub-boards and the big board alike.
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2], [3, 4, 5], [6, 7, 8],
+    [0, 3, 6], [1, 4, 7], [2, 5, 8],
+    [0, 4, 8], [2, 4, 6],
+];
+
+/// Ultimate Tic Tac Toe: nine Tic Tac Toe sub-boards in a 3x3 grid. The square played
+/// decides which sub-board the opponent has to play in next, unless that one is already
+/// decided, in which case they can play anywhere. Three sub-boards in a row wins.
+///
+/// Sub-boards and the squares in them are both numbered 1-9 from the top left, so the move
+/// `53` is square 3 of the center board. Internally a move is `board * 9 + square`.
+#[derive(Debug, Clone)]
+pub struct Ultimate {
+    pub boards: [[State; 9]; 9],
+    /// Who took each sub-board, `State::Draw` once one is full without a winner.
+    pub results: [State; 9],
+    /// The sub-board the player to move has to play in, `None` if it's their pick.
+    pub forced: Option<usize>,
+}
+
+fn line_winner(cells: &[State; 9]) -> State {
+    for line in LINES.iter() {
+        let first = cells[line[0]];
+        if (first == State::X || first == State::O) && line.iter().all(|&i| cells[i] == first) {
+            return first;
+        }
+    }
+    State::None
+}
+
+impl GameRules for Ultimate {
+    fn initial() -> Ultimate {
+        Ultimate {
+            boards: [[State::None; 9]; 9],
+            results: [State::None; 9],
+            forced: None,
+        }
+    }
+
+    fn parse_move(&self, input: &str) -> Option<usize> {
+        let digits = input.chars().map(|c| c.to_digit(10)).collect::<Option<Vec<u32>>>()?;
+        match digits.as_slice() {
+            [board @ 1..=9, square @ 1..=9] => Some((*board as usize - 1) * 9 + *square as usize - 1),
+            _ => None,
+        }
+    }
+
+    fn format_move(&self, mv: usize) -> String {
+        format!("{}{}", mv / 9 + 1, mv % 9 + 1)
+    }
+
+    fn move_count(&self) -> usize {
+        81
+    }
+
+    fn is_legal(&self, mv: usize) -> bool {
+        let (board, square) = (mv / 9, mv % 9);
+        self.outcome() == State::None
+            && self.results[board] == State::None
+            && self.forced.is_none_or(|forced| forced == board)
+            && self.boards[board][square] == State::None
+    }
+
+    fn apply(&mut self, mv: usize, side: State) {
+        let (board, square) = (mv / 9, mv % 9);
+        self.boards[board][square] = side;
+        let winner = line_winner(&self.boards[board]);
+        if winner != State::None {
+            self.results[board] = winner;
+        }
+        else if self.boards[board].iter().all(|&cell| cell != State::None) {
+            self.results[board] = State::Draw;
+        }
+        self.forced = if self.results[square] == State::None { Some(square) } else { None };
+    }
+
+    fn outcome(&self) -> State {
+        let winner = line_winner(&self.results);
+        if winner != State::None {
+            return winner;
+        }
+        if self.results.iter().all(|&result| result != State::None) {
+            return State::Draw;
+        }
+        State::None
+    }
+
+    /// The 9x9 grid of squares with `|` and `-` separating the sub-boards.
+    fn render(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        for board_row in 0..3 {
+            if board_row > 0 {
+                rows.push(vec!["-".to_string(); 11]);
+            }
+            for square_row in 0..3 {
+                let mut row = vec![];
+                for board_column in 0..3 {
+                    if board_column > 0 {
+                        row.push("|".to_string());
+                    }
+                    let board = &self.boards[board_row * 3 + board_column];
+                    row.extend(board[square_row * 3..square_row * 3 + 3].iter().map(|cell| format!("{:?}", cell)));
+                }
+                rows.push(row);
+            }
+        }
+        rows
+    }
+
+    fn move_hint(&self) -> String {
+        match self.forced {
+            Some(board) => format!("play in board {}, type {} followed by a square 1-9", board + 1, board + 1),
+            None => "play in any open board, type a board 1-9 followed by a square 1-9".to_string(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn GameRules> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills a sub-board without a line for either side.
+    const DRAW: [State; 9] = [
+        State::X, State::O, State::X,
+        State::X, State::O, State::O,
+        State::O, State::X, State::X,
+    ];
+
+    /// Takes the top row of `board` for `side`, ignoring where the game says to play.
+    fn take(game: &mut Ultimate, board: usize, side: State) {
+        for square in 0..3 {
+            game.apply(board * 9 + square, side);
+        }
+        assert_eq!(game.results[board], side);
+    }
+
+    /// Fills `board` with a draw, ignoring where the game says to play.
+    fn draw(game: &mut Ultimate, board: usize) {
+        for (square, side) in DRAW.iter().enumerate() {
+            game.apply(board * 9 + square, *side);
+        }
+        assert_eq!(game.results[board], State::Draw);
+    }
+
+    #[test]
+    fn the_square_played_picks_the_next_board() {
+        let mut game = Ultimate::initial();
+        assert!(game.is_legal(game.parse_move("11").unwrap()));
+        game.apply(game.parse_move("53").unwrap(), State::X);
+        assert_eq!(game.forced, Some(2));
+        assert!(!game.is_legal(game.parse_move("11").unwrap()));
+        assert!(!game.is_legal(game.parse_move("54").unwrap()));
+        assert!(game.is_legal(game.parse_move("35").unwrap()));
+        assert_eq!(game.format_move(game.parse_move("35").unwrap()), "35");
+    }
+
+    #[test]
+    fn a_decided_target_board_leaves_the_choice_open() {
+        let mut game = Ultimate::initial();
+        take(&mut game, 0, State::X);
+        game.apply(game.parse_move("51").unwrap(), State::O);
+        assert_eq!(game.forced, None);
+        assert!(game.is_legal(game.parse_move("99").unwrap()));
+        assert!(!game.is_legal(game.parse_move("19").unwrap()), "board 1 is already won");
+
+        let mut game = Ultimate::initial();
+        draw(&mut game, 1);
+        game.apply(game.parse_move("52").unwrap(), State::X);
+        assert_eq!(game.forced, None);
+        assert!(game.is_legal(game.parse_move("11").unwrap()));
+    }
+
+    #[test]
+    fn a_drawn_board_counts_for_neither_side() {
+        let mut game = Ultimate::initial();
+        take(&mut game, 0, State::X);
+        draw(&mut game, 1);
+        take(&mut game, 2, State::X);
+        assert_eq!(game.outcome(), State::None);
+    }
+
+    #[test]
+    fn three_boards_in_a_row_win() {
+        let mut game = Ultimate::initial();
+        take(&mut game, 0, State::O);
+        take(&mut game, 4, State::O);
+        assert_eq!(game.outcome(), State::None);
+        take(&mut game, 8, State::O);
+        assert_eq!(game.outcome(), State::O);
+        assert!(!game.is_legal(game.parse_move("21").unwrap()));
+    }
+}